@@ -0,0 +1,55 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use reqwest::header::{HeaderValue, AUTHORIZATION};
+use reqwest::Request;
+use url::Url;
+
+use crate::clock::BoxFuture;
+use crate::prelude::*;
+use crate::registry::{best_match, HostPattern};
+
+/// Provides the bearer token for requests, and a fresh one when the server rejects it.
+///
+/// When a provider is registered for a host, the client sets `Authorization: Bearer <token>` on
+/// every attempt, replacing any existing header. If the server responds with `401`, the token is
+/// refreshed once per call and the request is sent again, without counting as a retry.
+pub trait TokenProvider: Debug + Send + Sync {
+    /// The current access token.
+    fn token(&self) -> BoxFuture<'_, Result<String>>;
+
+    /// Gets a new access token after the current one was rejected.
+    fn refresh(&self) -> BoxFuture<'_, Result<String>>;
+}
+
+/// The token providers registered on a client, by host.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TokenProviders {
+    providers: Arc<Vec<(HostPattern, Arc<dyn TokenProvider>)>>,
+}
+
+impl TokenProviders {
+    pub(crate) fn new(providers: Vec<(HostPattern, Arc<dyn TokenProvider>)>) -> Self {
+        Self {
+            providers: Arc::new(providers),
+        }
+    }
+
+    pub(crate) fn get(&self, url: &Url) -> Option<&Arc<dyn TokenProvider>> {
+        best_match(&self.providers, url).map(|(_, provider)| provider)
+    }
+}
+
+/// Replaces the `Authorization` header of `request` with the bearer `token`.
+pub(crate) fn set_bearer_token(request: &mut Request, token: &str) -> Result<()> {
+    let mut value = HeaderValue::try_from(format!("Bearer {}", token)).map_err(|e| {
+        ReqwestBackoffError::InvalidHeader {
+            name: AUTHORIZATION.to_string(),
+            value: "<redacted>".to_string(),
+            reason: e.to_string(),
+        }
+    })?;
+    value.set_sensitive(true);
+    request.headers_mut().insert(AUTHORIZATION, value);
+    Ok(())
+}
@@ -0,0 +1,213 @@
+use std::fmt::{Debug, Formatter};
+use std::io::SeekFrom;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use reqwest::header::{HeaderValue, CONTENT_LENGTH};
+use reqwest::{Body, Request};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+use url::Url;
+
+use crate::prelude::*;
+
+type BodyFactory = Arc<dyn Fn() -> Body + Send + Sync>;
+
+/// Creates a fresh request body for every attempt, so requests with streamed bodies can be
+/// retried.
+#[derive(Clone)]
+pub enum BodySource {
+    /// Calls the closure for every attempt.
+    Factory(BodyFactory),
+    /// Streams the file at `path`, starting at byte `offset`, up to `len` bytes or the end of the
+    /// file.
+    ///
+    /// `Content-Length` is set to the streamed size, unless the request already has one.
+    File {
+        path: PathBuf,
+        offset: u64,
+        len: Option<u64>,
+    },
+}
+
+impl Debug for BodySource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BodySource::Factory(_) => f.write_str("Factory"),
+            BodySource::File { path, offset, len } => f
+                .debug_struct("File")
+                .field("path", path)
+                .field("offset", offset)
+                .field("len", len)
+                .finish(),
+        }
+    }
+}
+
+impl BodySource {
+    pub fn factory(factory: impl Fn() -> Body + Send + Sync + 'static) -> Self {
+        BodySource::Factory(Arc::new(factory))
+    }
+
+    /// Streams the whole file at `path`.
+    pub fn file(path: impl Into<PathBuf>) -> Self {
+        Self::file_from(path, 0)
+    }
+
+    /// Streams the file at `path`, starting at byte `offset`.
+    pub fn file_from(path: impl Into<PathBuf>, offset: u64) -> Self {
+        BodySource::File {
+            path: path.into(),
+            offset,
+            len: None,
+        }
+    }
+
+    /// Streams `len` bytes of the file at `path`, starting at byte `offset`.
+    pub fn file_range(path: impl Into<PathBuf>, offset: u64, len: u64) -> Self {
+        BodySource::File {
+            path: path.into(),
+            offset,
+            len: Some(len),
+        }
+    }
+
+    /// Creates the body, along with its length if it is known.
+    pub(crate) async fn create(&self) -> Result<(Body, Option<u64>)> {
+        match self {
+            BodySource::Factory(factory) => Ok((factory(), None)),
+            BodySource::File { path, offset, len } => {
+                let mut file = tokio::fs::File::open(path).await?;
+                let remaining = file.metadata().await?.len().saturating_sub(*offset);
+                let len = len.map_or(remaining, |len| len.min(remaining));
+                file.seek(SeekFrom::Start(*offset)).await?;
+                let stream = ReaderStream::new(file.take(len));
+                Ok((Body::wrap_stream(stream), Some(len)))
+            }
+        }
+    }
+}
+
+/// Creates the request for each attempt of a call.
+pub(crate) struct RequestSource {
+    /// A request that can be cloned, because it has no body or a buffered one.
+    template: Request,
+    body: Option<BodySource>,
+    /// The body of a request that can't be cloned, taken by its only attempt.
+    single_body: Option<Mutex<Option<Body>>>,
+    /// Sent instead of the template after the first attempt, see [`retry_with`](Self::retry_with).
+    retry: Option<Request>,
+    sent: AtomicBool,
+}
+
+impl Debug for RequestSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RequestSource")
+            .field("template", &self.template)
+            .field("body", &self.body)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RequestSource {
+    /// Uses clones of `request` for every attempt, or sends it once if it can't be cloned.
+    pub(crate) fn new(request: Request) -> Self {
+        if request.try_clone().is_some() {
+            return Self::cloneable(request);
+        }
+        warn!("Failed to clone request. No backoff possible. Use execute_with_body for streamed bodies.");
+        Self::single(request)
+    }
+
+    /// Uses clones of `request` for every attempt. `request` must be cloneable.
+    fn cloneable(request: Request) -> Self {
+        debug_assert!(request.try_clone().is_some());
+        Self {
+            template: request,
+            body: None,
+            single_body: None,
+            retry: None,
+            sent: AtomicBool::new(false),
+        }
+    }
+
+    /// Sends `request` in a single attempt, for requests that can't be cloned.
+    fn single(mut request: Request) -> Self {
+        let body = request.body_mut().take();
+        Self {
+            template: request,
+            body: None,
+            single_body: Some(Mutex::new(body)),
+            retry: None,
+            sent: AtomicBool::new(false),
+        }
+    }
+
+    /// Uses `body` for every attempt, replacing the body of `request`.
+    pub(crate) fn with_body(mut request: Request, body: BodySource) -> Self {
+        *request.body_mut() = None;
+        Self {
+            template: request,
+            body: Some(body),
+            single_body: None,
+            retry: None,
+            sent: AtomicBool::new(false),
+        }
+    }
+
+    /// Sends `retry` instead of the template for all attempts after the first one, for requests
+    /// that have to change after a failure. `retry` must be cloneable.
+    pub(crate) fn retry_with(mut self, retry: Request) -> Self {
+        debug_assert!(retry.try_clone().is_some());
+        self.retry = Some(retry);
+        self
+    }
+
+    /// Whether the request can be sent more than once.
+    pub(crate) fn can_repeat(&self) -> bool {
+        self.single_body.is_none()
+    }
+
+    pub(crate) fn url(&self) -> &Url {
+        self.template.url()
+    }
+
+    /// The request without its body, for inspecting the method and headers.
+    pub(crate) fn template(&self) -> &Request {
+        &self.template
+    }
+
+    pub(crate) fn template_mut(&mut self) -> &mut Request {
+        &mut self.template
+    }
+
+    /// Creates the request for the next attempt.
+    pub(crate) async fn next(&self) -> Result<Request> {
+        if let Some(retry) = &self.retry {
+            if self.sent.swap(true, Ordering::Relaxed) {
+                return Ok(retry
+                    .try_clone()
+                    .expect("the retry request of a RequestSource can always be cloned"));
+            }
+        }
+        let mut request = self
+            .template
+            .try_clone()
+            .expect("the template of a RequestSource can always be cloned");
+        if let Some(single_body) = &self.single_body {
+            *request.body_mut() = single_body.lock().unwrap().take();
+        }
+        if let Some(body) = &self.body {
+            let (body, len) = body.create().await?;
+            *request.body_mut() = Some(body);
+            if let Some(len) = len {
+                request
+                    .headers_mut()
+                    .entry(CONTENT_LENGTH)
+                    .or_insert_with(|| HeaderValue::from(len));
+            }
+        }
+        Ok(request)
+    }
+}
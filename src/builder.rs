@@ -0,0 +1,295 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::auth::{TokenProvider, TokenProviders};
+use crate::circuit::{CircuitBreakerConfig, CircuitBreakers};
+use crate::clock::{Clock, Sleeper, SystemClock, TokioSleeper};
+use crate::config::BackoffConfig;
+use crate::hooks::{Hooks, RetryAction, RetryEvent};
+use crate::idempotency::UnsafeRetries;
+use crate::policy::{BackoffPolicy, DefaultPolicy, GooglePolicy, TwitchPolicy};
+use crate::prelude::*;
+use crate::rate_limit::{RateLimit, RateLimiter};
+use crate::registry::{HostPattern, PolicyRegistry};
+use crate::ReqwestClient;
+
+enum ClientSource {
+    Client(reqwest::Client),
+    Builder(Box<reqwest::ClientBuilder>),
+}
+
+/// Builder for a [`ReqwestClient`] with custom limits and policies.
+///
+/// Everything is validated when calling [`build`](Self::build).
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// # use twba_reqwest_backoff::{BackoffConfig, ReqwestClient};
+/// # fn main() -> Result<(), twba_reqwest_backoff::ReqwestBackoffError> {
+/// let client = ReqwestClient::builder()
+///     .google(BackoffConfig::google().with_max_attempts(10))
+///     .max_elapsed(Duration::from_secs(15 * 60))
+///     .build()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct ReqwestClientBuilder {
+    client: ClientSource,
+    twitch: BackoffConfig,
+    google: BackoffConfig,
+    fallback: BackoffConfig,
+    policies: Vec<(String, Arc<dyn BackoffPolicy>)>,
+    max_elapsed: Option<Duration>,
+    attempt_timeout: Option<Duration>,
+    rate_limits: Vec<(String, RateLimit)>,
+    circuit_breakers: Vec<(String, CircuitBreakerConfig)>,
+    clock: Arc<dyn Clock>,
+    sleeper: Arc<dyn Sleeper>,
+    hooks: Hooks,
+    token_providers: Vec<(String, Arc<dyn TokenProvider>)>,
+    unsafe_retries: UnsafeRetries,
+}
+
+impl Default for ReqwestClientBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ReqwestClientBuilder {
+    pub fn new() -> Self {
+        Self {
+            client: ClientSource::Builder(Box::new(reqwest::Client::builder())),
+            twitch: BackoffConfig::twitch(),
+            google: BackoffConfig::google(),
+            fallback: BackoffConfig::default(),
+            policies: Vec::new(),
+            max_elapsed: None,
+            attempt_timeout: None,
+            rate_limits: Vec::new(),
+            circuit_breakers: Vec::new(),
+            clock: Arc::new(SystemClock),
+            sleeper: Arc::new(TokioSleeper),
+            hooks: Hooks::default(),
+            token_providers: Vec::new(),
+            unsafe_retries: UnsafeRetries::default(),
+        }
+    }
+
+    /// Wrap an existing client instead of creating a new one.
+    pub fn client(mut self, client: reqwest::Client) -> Self {
+        self.client = ClientSource::Client(client);
+        self
+    }
+
+    /// Create the wrapped client from this builder.
+    pub fn client_builder(mut self, builder: reqwest::ClientBuilder) -> Self {
+        self.client = ClientSource::Builder(Box::new(builder));
+        self
+    }
+
+    /// The limits for the built-in Twitch policy.
+    pub fn twitch(mut self, config: BackoffConfig) -> Self {
+        self.twitch = config;
+        self
+    }
+
+    /// The limits for the built-in Google and YouTube policy.
+    pub fn google(mut self, config: BackoffConfig) -> Self {
+        self.google = config;
+        self
+    }
+
+    /// The limits for hosts without a dedicated policy.
+    pub fn fallback(mut self, config: BackoffConfig) -> Self {
+        self.fallback = config;
+        self
+    }
+
+    /// Use `policy` for requests matching `pattern` (see [`HostPattern`] for the syntax).
+    ///
+    /// Policies added here take precedence over built-in entries with the same pattern.
+    pub fn policy(
+        mut self,
+        pattern: impl Into<String>,
+        policy: impl BackoffPolicy + 'static,
+    ) -> Self {
+        self.policies.push((pattern.into(), Arc::new(policy)));
+        self
+    }
+
+    /// The maximum total time a single call may take, including all attempts and delays.
+    ///
+    /// Once it is reached, the call fails with [`ReqwestBackoffError::DeadlineExceeded`].
+    pub fn max_elapsed(mut self, max_elapsed: Duration) -> Self {
+        self.max_elapsed = Some(max_elapsed);
+        self
+    }
+
+    /// The maximum time a single attempt may take until the response headers arrive.
+    ///
+    /// Once it is reached, the call fails with [`ReqwestBackoffError::AttemptTimeout`].
+    pub fn attempt_timeout(mut self, attempt_timeout: Duration) -> Self {
+        self.attempt_timeout = Some(attempt_timeout);
+        self
+    }
+
+    /// Limit the requests sent to hosts matching `pattern` with a token bucket.
+    ///
+    /// The bucket is shared by all clones of the client and all hosts matching the pattern. If
+    /// responses carry Twitch style `Ratelimit-*` headers, the bucket is synced with them.
+    pub fn rate_limit(mut self, pattern: impl Into<String>, limit: RateLimit) -> Self {
+        self.rate_limits.push((pattern.into(), limit));
+        self
+    }
+
+    /// Stop sending requests to hosts matching `pattern` while they keep failing.
+    ///
+    /// The breaker is shared by all clones of the client and all hosts matching the pattern, so
+    /// concurrent tasks stop together instead of each walking through its own backoff.
+    pub fn circuit_breaker(
+        mut self,
+        pattern: impl Into<String>,
+        config: CircuitBreakerConfig,
+    ) -> Self {
+        self.circuit_breakers.push((pattern.into(), config));
+        self
+    }
+
+    /// The source of the current time. Defaults to [`SystemClock`].
+    pub fn clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// How to wait between attempts. Defaults to [`TokioSleeper`].
+    ///
+    /// Pass the same [`MockClock`](crate::clock::MockClock) here and to [`clock`](Self::clock)
+    /// to run the retry loop without waiting in real time.
+    pub fn sleeper(mut self, sleeper: impl Sleeper + 'static) -> Self {
+        self.sleeper = Arc::new(sleeper);
+        self
+    }
+
+    /// Authenticate requests matching `pattern` with bearer tokens from `provider`.
+    ///
+    /// See [`TokenProvider`] for how tokens are used and refreshed.
+    pub fn token_provider(
+        mut self,
+        pattern: impl Into<String>,
+        provider: impl TokenProvider + 'static,
+    ) -> Self {
+        self.token_providers
+            .push((pattern.into(), Arc::new(provider)));
+        self
+    }
+
+    /// How requests with methods that are not idempotent, like `POST`, are retried.
+    ///
+    /// Defaults to [`UnsafeRetries::BeforeSent`].
+    pub fn unsafe_retries(mut self, unsafe_retries: UnsafeRetries) -> Self {
+        self.unsafe_retries = unsafe_retries;
+        self
+    }
+
+    /// Called before sleeping for a retry. Returning [`RetryAction::Abort`] stops retrying.
+    ///
+    /// The call waits for the hook, so it can be used to refresh credentials before the retry.
+    pub fn on_retry<F, Fut>(mut self, hook: F) -> Self
+    where
+        F: Fn(RetryEvent) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = RetryAction> + Send + 'static,
+    {
+        self.hooks.set_on_retry(hook);
+        self
+    }
+
+    /// Called when a call fails, with the outcome of its last attempt.
+    pub fn on_give_up<F, Fut>(mut self, hook: F) -> Self
+    where
+        F: Fn(RetryEvent) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.hooks.set_on_give_up(hook);
+        self
+    }
+
+    /// Called when a call returns a response, which is not necessarily a success status.
+    pub fn on_success<F, Fut>(mut self, hook: F) -> Self
+    where
+        F: Fn(RetryEvent) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.hooks.set_on_success(hook);
+        self
+    }
+
+    pub fn build(self) -> Result<ReqwestClient> {
+        self.twitch.validate()?;
+        self.google.validate()?;
+        self.fallback.validate()?;
+        if self.max_elapsed == Some(Duration::ZERO) {
+            return Err(ReqwestBackoffError::InvalidConfig(
+                "max_elapsed must be greater than zero".to_string(),
+            ));
+        }
+        if self.attempt_timeout == Some(Duration::ZERO) {
+            return Err(ReqwestBackoffError::InvalidConfig(
+                "attempt_timeout must be greater than zero".to_string(),
+            ));
+        }
+
+        let mut registry = PolicyRegistry::builtin(
+            TwitchPolicy::new(self.twitch),
+            GooglePolicy::new(self.google),
+            DefaultPolicy::new(self.fallback),
+        );
+        for (pattern, policy) in self.policies {
+            registry.insert_arc(HostPattern::parse(&pattern)?, policy);
+        }
+
+        let rate_limits = self
+            .rate_limits
+            .into_iter()
+            .map(|(pattern, limit)| {
+                limit.validate()?;
+                Ok((HostPattern::parse(&pattern)?, limit))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let circuit_breakers = self
+            .circuit_breakers
+            .into_iter()
+            .map(|(pattern, config)| {
+                config.validate()?;
+                Ok((HostPattern::parse(&pattern)?, config))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let token_providers = self
+            .token_providers
+            .into_iter()
+            .map(|(pattern, provider)| Ok((HostPattern::parse(&pattern)?, provider)))
+            .collect::<Result<Vec<_>>>()?;
+
+        let client = match self.client {
+            ClientSource::Client(client) => client,
+            ClientSource::Builder(builder) => builder.build()?,
+        };
+
+        Ok(ReqwestClient {
+            client,
+            registry,
+            max_elapsed: self.max_elapsed,
+            attempt_timeout: self.attempt_timeout,
+            rate_limiter: RateLimiter::new(rate_limits, self.clock.now()),
+            circuit_breakers: CircuitBreakers::new(circuit_breakers),
+            clock: self.clock,
+            sleeper: self.sleeper,
+            hooks: self.hooks,
+            token_providers: TokenProviders::new(token_providers),
+            unsafe_retries: self.unsafe_retries,
+        })
+    }
+}
@@ -0,0 +1,307 @@
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use url::Url;
+
+use crate::clock::Clock;
+use crate::prelude::*;
+use crate::registry::{best_match, HostPattern};
+
+/// Configures when a circuit breaker stops requests to a failing host.
+///
+/// An attempt counts as failed if it ends with a transport error or a response the policy wants to
+/// retry. Once the ratio of failures among the recent attempts reaches `failure_ratio`, the breaker
+/// opens and requests fail fast with [`ReqwestBackoffError::CircuitOpen`]. After `open_duration`,
+/// it lets `half_open_requests` trial requests through and closes again if all of them succeed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CircuitBreakerConfig {
+    /// The ratio of failed attempts, between 0 and 1, at which the breaker opens.
+    pub failure_ratio: f64,
+    /// How many of the most recent attempts the ratio is computed over.
+    pub window: u32,
+    /// The minimum number of attempts in the window before the breaker may open.
+    pub min_requests: u32,
+    /// How long the breaker stays open before letting trial requests through.
+    pub open_duration: Duration,
+    /// How many trial requests have to succeed in the half-open state to close the breaker.
+    pub half_open_requests: u32,
+}
+
+impl CircuitBreakerConfig {
+    pub fn new(failure_ratio: f64, open_duration: Duration) -> Self {
+        Self {
+            failure_ratio,
+            open_duration,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_window(mut self, window: u32, min_requests: u32) -> Self {
+        self.window = window;
+        self.min_requests = min_requests;
+        self
+    }
+
+    pub fn with_half_open_requests(mut self, half_open_requests: u32) -> Self {
+        self.half_open_requests = half_open_requests;
+        self
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if !(self.failure_ratio > 0.0 && self.failure_ratio <= 1.0) {
+            return Err(ReqwestBackoffError::InvalidConfig(
+                "failure_ratio must be greater than zero and at most one".to_string(),
+            ));
+        }
+        if self.window == 0 || self.min_requests == 0 || self.min_requests > self.window {
+            return Err(ReqwestBackoffError::InvalidConfig(
+                "min_requests must be at least 1 and at most window".to_string(),
+            ));
+        }
+        if self.half_open_requests == 0 {
+            return Err(ReqwestBackoffError::InvalidConfig(
+                "half_open_requests must be at least 1".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Default for CircuitBreakerConfig {
+    /// Opens when half of the last 20 attempts (at least 10) failed, for 30 seconds.
+    fn default() -> Self {
+        Self {
+            failure_ratio: 0.5,
+            window: 20,
+            min_requests: 10,
+            open_duration: Duration::from_secs(30),
+            half_open_requests: 1,
+        }
+    }
+}
+
+/// The state of a circuit breaker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Requests are sent and their outcomes recorded.
+    Closed,
+    /// Requests fail fast until the given time.
+    Open { until: DateTime<Utc> },
+    /// A limited number of trial requests is sent to check whether the host recovered.
+    HalfOpen,
+}
+
+impl Display for CircuitState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CircuitState::Closed => write!(f, "closed"),
+            CircuitState::Open { until } => write!(f, "open until {}", until),
+            CircuitState::HalfOpen => write!(f, "half-open"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Breaker {
+    config: CircuitBreakerConfig,
+    state: CircuitState,
+    /// The outcomes of the most recent attempts in the closed state, `true` for failures.
+    outcomes: VecDeque<bool>,
+    /// Trial requests sent and succeeded in the half-open state.
+    trials: u32,
+    successes: u32,
+}
+
+impl Breaker {
+    fn new(config: CircuitBreakerConfig) -> Self {
+        Self {
+            config,
+            state: CircuitState::Closed,
+            outcomes: VecDeque::new(),
+            trials: 0,
+            successes: 0,
+        }
+    }
+
+    fn transition(&mut self, pattern: &HostPattern, state: CircuitState) {
+        match state {
+            CircuitState::Open { .. } => {
+                warn!(%pattern, %state, "Circuit breaker opened after too many failures")
+            }
+            CircuitState::HalfOpen => {
+                info!(%pattern, %state, "Circuit breaker sends trial requests")
+            }
+            CircuitState::Closed => info!(%pattern, %state, "Circuit breaker closed"),
+        }
+        self.state = state;
+        self.outcomes.clear();
+        self.trials = 0;
+        self.successes = 0;
+    }
+
+    /// Returns `Err` with the time until the breaker lets requests through if it is open.
+    fn acquire(
+        &mut self,
+        pattern: &HostPattern,
+        now: DateTime<Utc>,
+    ) -> std::result::Result<(), Duration> {
+        if let CircuitState::Open { until } = self.state {
+            if now < until {
+                return Err((until - now).to_std().unwrap_or(Duration::ZERO));
+            }
+            self.transition(pattern, CircuitState::HalfOpen);
+        }
+        if self.state == CircuitState::HalfOpen {
+            if self.trials >= self.config.half_open_requests {
+                return Err(Duration::ZERO);
+            }
+            self.trials += 1;
+        }
+        Ok(())
+    }
+
+    /// Frees the trial request taken by [`acquire`](Self::acquire).
+    fn release(&mut self) {
+        if self.state == CircuitState::HalfOpen {
+            self.trials = self.trials.saturating_sub(1);
+        }
+    }
+
+    fn record(&mut self, pattern: &HostPattern, failed: bool, now: DateTime<Utc>) {
+        let open = CircuitState::Open {
+            until: now + self.config.open_duration,
+        };
+        match self.state {
+            CircuitState::Closed => {
+                self.outcomes.push_back(failed);
+                if self.outcomes.len() > self.config.window as usize {
+                    self.outcomes.pop_front();
+                }
+                let failures = self.outcomes.iter().filter(|failed| **failed).count();
+                if self.outcomes.len() >= self.config.min_requests as usize
+                    && failures as f64 / self.outcomes.len() as f64 >= self.config.failure_ratio
+                {
+                    self.transition(pattern, open);
+                }
+            }
+            CircuitState::HalfOpen if failed => self.transition(pattern, open),
+            CircuitState::HalfOpen => {
+                self.successes += 1;
+                if self.successes >= self.config.half_open_requests {
+                    self.transition(pattern, CircuitState::Closed);
+                }
+            }
+            // Attempts that were already running when the breaker opened
+            CircuitState::Open { .. } => {}
+        }
+    }
+}
+
+/// Permission from a circuit breaker to send an attempt.
+///
+/// The outcome of the attempt has to be passed to [`record`](Self::record), or the permit given
+/// back with [`release`](Self::release) if the attempt never reached the host. If the permit is
+/// dropped instead, for example because the call was cancelled, the attempt counts as failed, so a
+/// trial request can't leave the breaker half-open.
+#[must_use]
+pub(crate) struct Permit {
+    breaker: Option<(HostPattern, Arc<Mutex<Breaker>>)>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Permit {
+    /// Records the outcome of the attempt.
+    pub(crate) fn record(mut self, failed: bool) {
+        self.record_outcome(failed);
+    }
+
+    /// Gives the permit back without recording an outcome, for attempts that failed before
+    /// reaching the host.
+    pub(crate) fn release(mut self) {
+        if let Some((_, breaker)) = self.breaker.take() {
+            if let Ok(mut breaker) = breaker.lock() {
+                breaker.release();
+            }
+        }
+    }
+
+    fn record_outcome(&mut self, failed: bool) {
+        if let Some((pattern, breaker)) = self.breaker.take() {
+            if let Ok(mut breaker) = breaker.lock() {
+                breaker.record(&pattern, failed, self.clock.now());
+            }
+        }
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if self.breaker.is_some() {
+            info!("Attempt ended without an outcome, recording it as failed");
+            self.record_outcome(true);
+        }
+    }
+}
+
+/// Per-host circuit breakers, shared between all clones of a [`ReqwestClient`](crate::ReqwestClient).
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CircuitBreakers {
+    breakers: Arc<Vec<(HostPattern, Arc<Mutex<Breaker>>)>>,
+}
+
+impl CircuitBreakers {
+    pub(crate) fn new(configs: Vec<(HostPattern, CircuitBreakerConfig)>) -> Self {
+        let breakers = configs
+            .into_iter()
+            .map(|(pattern, config)| (pattern, Arc::new(Mutex::new(Breaker::new(config)))))
+            .collect();
+        Self {
+            breakers: Arc::new(breakers),
+        }
+    }
+
+    /// Fails with [`ReqwestBackoffError::CircuitOpen`] if the breaker for `url` is open.
+    pub(crate) fn acquire(&self, url: &Url, clock: &Arc<dyn Clock>) -> Result<Permit> {
+        let Some((pattern, breaker)) = best_match(&self.breakers, url) else {
+            return Ok(Permit {
+                breaker: None,
+                clock: clock.clone(),
+            });
+        };
+        breaker
+            .lock()
+            .unwrap()
+            .acquire(pattern, clock.now())
+            .map_err(|wait| circuit_open(url, wait))?;
+        Ok(Permit {
+            breaker: Some((pattern.clone(), breaker.clone())),
+            clock: clock.clone(),
+        })
+    }
+
+    /// Fails with [`ReqwestBackoffError::CircuitOpen`] if the breaker for `url` is open, without
+    /// taking a trial request.
+    pub(crate) fn check(&self, url: &Url, now: DateTime<Utc>) -> Result<()> {
+        match self.state(url) {
+            Some(CircuitState::Open { until }) if now < until => Err(circuit_open(
+                url,
+                (until - now).to_std().unwrap_or(Duration::ZERO),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn state(&self, url: &Url) -> Option<CircuitState> {
+        best_match(&self.breakers, url).map(|(_, breaker)| breaker.lock().unwrap().state)
+    }
+}
+
+fn circuit_open(url: &Url, wait: Duration) -> ReqwestBackoffError {
+    ReqwestBackoffError::CircuitOpen {
+        host: url.host_str().unwrap_or_default().to_string(),
+        retry_after: (!wait.is_zero()).then_some(wait),
+    }
+}
@@ -0,0 +1,156 @@
+use std::fmt::Debug;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::Poll;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// The source of the current time for rate limit resets, `Retry-After` dates and deadlines.
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// Waits between attempts.
+pub trait Sleeper: Debug + Send + Sync {
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()>;
+
+    /// Completes after `duration` to time out an attempt that is still in flight.
+    ///
+    /// Defaults to [`sleep`](Self::sleep). Unlike `sleep`, this races with a request, so it should
+    /// not complete before the time actually passed.
+    fn timer(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        self.sleep(duration)
+    }
+}
+
+/// Runs `future` until it completes or the timer of `sleeper` for `duration` fires, in which case
+/// `None` is returned.
+pub(crate) async fn timeout<F: Future>(
+    sleeper: &dyn Sleeper,
+    duration: Duration,
+    future: F,
+) -> Option<F::Output> {
+    let mut future = std::pin::pin!(future);
+    let mut timer = sleeper.timer(duration);
+    std::future::poll_fn(|cx| {
+        if let Poll::Ready(output) = future.as_mut().poll(cx) {
+            return Poll::Ready(Some(output));
+        }
+        timer.as_mut().poll(cx).map(|()| None)
+    })
+    .await
+}
+
+/// The system time, as reported by [`chrono`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Sleeps with [`tokio::time::sleep`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokioSleeper;
+
+impl Sleeper for TokioSleeper {
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+#[derive(Debug)]
+struct MockClockState {
+    now: DateTime<Utc>,
+    sleeps: Vec<Duration>,
+}
+
+/// A [`Clock`] and [`Sleeper`] for tests that only moves when told to.
+///
+/// Sleeping returns immediately, advances the time by the requested duration and records it, so
+/// tests can assert on the exact delays. Clones share the same time.
+///
+/// Attempt timeouts can't fire before the response arrives, so their [`Sleeper::timer`] waits in
+/// real time and then advances the time by the full timeout, without recording a sleep.
+///
+/// ```
+/// # use std::time::Duration;
+/// # use twba_reqwest_backoff::clock::{Clock, MockClock, Sleeper};
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let clock = MockClock::new(chrono::DateTime::UNIX_EPOCH);
+/// clock.sleep(Duration::from_secs(2)).await;
+/// assert_eq!(clock.now().timestamp(), 2);
+/// assert_eq!(clock.sleeps(), vec![Duration::from_secs(2)]);
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    state: Arc<Mutex<MockClockState>>,
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new(Utc::now())
+    }
+}
+
+impl MockClock {
+    pub fn new(now: DateTime<Utc>) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(MockClockState {
+                now,
+                sleeps: Vec::new(),
+            })),
+        }
+    }
+
+    /// Moves the time forward without recording a sleep.
+    pub fn advance(&self, duration: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.now += duration;
+    }
+
+    pub fn set(&self, now: DateTime<Utc>) {
+        self.state.lock().unwrap().now = now;
+    }
+
+    /// All durations that were slept so far, in order.
+    pub fn sleeps(&self) -> Vec<Duration> {
+        self.state.lock().unwrap().sleeps.clone()
+    }
+
+    /// The sum of all durations that were slept so far.
+    pub fn total_slept(&self) -> Duration {
+        self.state.lock().unwrap().sleeps.iter().sum()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.state.lock().unwrap().now
+    }
+}
+
+impl Sleeper for MockClock {
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        let mut state = self.state.lock().unwrap();
+        state.now += duration;
+        state.sleeps.push(duration);
+        Box::pin(std::future::ready(()))
+    }
+
+    fn timer(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        let clock = self.clone();
+        Box::pin(async move {
+            tokio::time::sleep(duration).await;
+            clock.advance(duration);
+        })
+    }
+}
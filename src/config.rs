@@ -0,0 +1,217 @@
+use std::error::Error as _;
+use std::time::Duration;
+
+use crate::jitter::{Jitter, JitterRng};
+use crate::policy::RetryState;
+use crate::prelude::*;
+
+const DEFAULT_MAX_RETRY_AFTER: Duration = Duration::from_secs(3600);
+
+/// Limits and delays for an exponential backoff.
+///
+/// The delay before attempt `n + 1` is `base_delay * multiplier^(n - 1)`, capped at `max_delay`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BackoffConfig {
+    /// The maximum number of attempts, including the first one. `1` disables retries.
+    pub max_attempts: u32,
+    /// The delay after the first failed attempt.
+    pub base_delay: Duration,
+    /// The factor the delay grows by with each attempt.
+    pub multiplier: f64,
+    /// The upper bound for a single delay.
+    pub max_delay: Duration,
+    /// The upper bound for a delay requested by the server with `Retry-After`.
+    pub max_retry_after: Duration,
+    /// Which transport errors are retried.
+    pub retry_on: TransportErrors,
+    /// How the computed delays are randomized.
+    pub jitter: Jitter,
+    /// The seed for the jitter, to make the delays reproducible. Randomly seeded if `None`.
+    pub jitter_seed: Option<u64>,
+}
+
+/// Kinds of [`reqwest::Error`]s that can be retried, because they are usually transient.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransportErrors {
+    /// Failures to connect, including DNS resolution ([`reqwest::Error::is_connect`]).
+    pub connect: bool,
+    /// Timeouts ([`reqwest::Error::is_timeout`]).
+    pub timeout: bool,
+    /// I/O errors while sending the request, like a connection reset while the body is written
+    /// ([`reqwest::Error::is_request`] or [`reqwest::Error::is_body`] caused by an I/O error).
+    pub io: bool,
+}
+
+impl Default for TransportErrors {
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+impl TransportErrors {
+    pub fn all() -> Self {
+        Self {
+            connect: true,
+            timeout: true,
+            io: true,
+        }
+    }
+
+    pub fn none() -> Self {
+        Self {
+            connect: false,
+            timeout: false,
+            io: false,
+        }
+    }
+
+    /// Returns `true` if `error` is one of the enabled kinds.
+    pub fn matches(&self, error: &reqwest::Error) -> bool {
+        if error.is_connect() {
+            return self.connect;
+        }
+        if error.is_timeout() {
+            return self.timeout;
+        }
+        if error.is_request() || error.is_body() {
+            return self.io && is_caused_by_io_error(error);
+        }
+        false
+    }
+}
+
+pub(crate) fn is_caused_by_io_error(error: &reqwest::Error) -> bool {
+    let mut source = error.source();
+    while let Some(error) = source {
+        if error.is::<std::io::Error>() {
+            return true;
+        }
+        source = error.source();
+    }
+    false
+}
+
+impl Default for BackoffConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 50,
+            base_delay: Duration::from_secs(5),
+            multiplier: 1.0,
+            max_delay: Duration::from_secs(5),
+            max_retry_after: DEFAULT_MAX_RETRY_AFTER,
+            retry_on: TransportErrors::default(),
+            jitter: Jitter::None,
+            jitter_seed: None,
+        }
+    }
+}
+
+impl BackoffConfig {
+    /// The defaults used for the Twitch API.
+    pub fn twitch() -> Self {
+        Self {
+            max_attempts: 50,
+            base_delay: Duration::from_secs(1),
+            multiplier: 2.0,
+            max_delay: Duration::from_secs(60),
+            max_retry_after: DEFAULT_MAX_RETRY_AFTER,
+            retry_on: TransportErrors::default(),
+            jitter: Jitter::None,
+            jitter_seed: None,
+        }
+    }
+
+    /// The defaults used for Google APIs.
+    pub fn google() -> Self {
+        Self {
+            max_attempts: 50,
+            base_delay: Duration::from_secs(2),
+            multiplier: 2.0,
+            max_delay: Duration::from_secs(3600),
+            max_retry_after: DEFAULT_MAX_RETRY_AFTER,
+            retry_on: TransportErrors::default(),
+            jitter: Jitter::None,
+            jitter_seed: None,
+        }
+    }
+
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    pub fn with_base_delay(mut self, base_delay: Duration) -> Self {
+        self.base_delay = base_delay;
+        self
+    }
+
+    pub fn with_multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    pub fn with_max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    pub fn with_max_retry_after(mut self, max_retry_after: Duration) -> Self {
+        self.max_retry_after = max_retry_after;
+        self
+    }
+
+    pub fn with_retry_on(mut self, retry_on: TransportErrors) -> Self {
+        self.retry_on = retry_on;
+        self
+    }
+
+    pub fn with_jitter(mut self, jitter: Jitter) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    pub fn with_jitter_seed(mut self, seed: u64) -> Self {
+        self.jitter_seed = Some(seed);
+        self
+    }
+
+    /// The delay after the given attempt (starting at 1) failed.
+    pub fn delay_for_attempt(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let factor = self.multiplier.powi(exponent);
+        let delay = self.base_delay.as_secs_f64() * factor;
+        if !delay.is_finite() || delay >= self.max_delay.as_secs_f64() {
+            self.max_delay
+        } else {
+            Duration::from_secs_f64(delay)
+        }
+    }
+
+    /// The delay after the attempt described by `state` failed, with [`jitter`](Self::jitter)
+    /// applied.
+    pub(crate) fn jittered_delay(&self, state: &RetryState, rng: &JitterRng) -> Duration {
+        let delay = self.delay_for_attempt(state.attempt);
+        self.jitter.apply(
+            delay,
+            self.base_delay,
+            self.max_delay,
+            state.previous_delay,
+            rng,
+        )
+    }
+
+    /// Checks that the values make sense together.
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |message: &str| Err(ReqwestBackoffError::InvalidConfig(message.to_string()));
+        if self.max_attempts == 0 {
+            return invalid("max_attempts must be at least 1");
+        }
+        if !self.multiplier.is_finite() || self.multiplier < 1.0 {
+            return invalid("multiplier must be a finite number of at least 1.0");
+        }
+        if self.base_delay > self.max_delay {
+            return invalid("base_delay must not be greater than max_delay");
+        }
+        Ok(())
+    }
+}
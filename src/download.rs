@@ -0,0 +1,221 @@
+//! Downloads that resume with a `Range` request when the connection drops in the middle of the body.
+
+use std::path::Path;
+
+use reqwest::header::{
+    HeaderMap, HeaderValue, CONTENT_RANGE, ETAG, IF_RANGE, LAST_MODIFIED, RANGE,
+};
+use reqwest::{Request, Response, StatusCode};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::prelude::*;
+use crate::resume::{is_interrupted_body, ResumableCall};
+use crate::ReqwestClient;
+
+/// Identifies the version of a resource, to make sure a resumed download continues the same one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Validator {
+    ETag(HeaderValue),
+    LastModified(HeaderValue),
+}
+
+impl Validator {
+    /// Prefers a strong `ETag`, since weak ones can't be used with `If-Range`.
+    fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        if let Some(etag) = headers.get(ETAG) {
+            if !etag.as_bytes().starts_with(b"W/") {
+                return Some(Validator::ETag(etag.clone()));
+            }
+        }
+        headers
+            .get(LAST_MODIFIED)
+            .map(|modified| Validator::LastModified(modified.clone()))
+    }
+
+    fn value(&self) -> &HeaderValue {
+        match self {
+            Validator::ETag(value) | Validator::LastModified(value) => value,
+        }
+    }
+
+    /// Returns `false` if the headers of a resumed response name a different version.
+    fn matches(&self, headers: &HeaderMap) -> bool {
+        let name = match self {
+            Validator::ETag(_) => ETAG,
+            Validator::LastModified(_) => LAST_MODIFIED,
+        };
+        headers.get(name).is_none_or(|value| value == self.value())
+    }
+}
+
+impl ReqwestClient {
+    /// Sends `request` with backoff and writes the body to `writer`, returning the number of bytes
+    /// written.
+    ///
+    /// If the body fails in the middle, the download continues with a `Range` request from the
+    /// last byte received. The request carries the `ETag` or `Last-Modified` of the first response
+    /// in `If-Range`, so if the resource changed in between, this fails with
+    /// [`ReqwestBackoffError::ResourceChanged`] instead of mixing two versions. A server that
+    /// answers without a validator and ignores the range fails with
+    /// [`ReqwestBackoffError::RangeNotSupported`].
+    ///
+    /// `request` may ask for a single range itself, like `bytes=1000-` or `bytes=1000-1999`. The
+    /// download then resumes within that range. Other ranges fail with
+    /// [`ReqwestBackoffError::InvalidHeader`] before anything is sent.
+    ///
+    /// The interruptions are delayed by the policy for the host, like transport errors. All
+    /// requests of the download share the policy's attempts and the maximum elapsed time.
+    /// [`RequestOverrides`](crate::RequestOverrides) in the extensions of the request apply to
+    /// all of them.
+    #[tracing::instrument(skip(self, writer))]
+    pub async fn download_with_backoff<W>(&self, request: Request, writer: &mut W) -> Result<u64>
+    where
+        W: AsyncWrite + Unpin + ?Sized,
+    {
+        let (mut call, request) = ResumableCall::with_overrides(self, request)?;
+        let requested = requested_range(&request)?;
+        let template = request.try_clone();
+        let url = request.url().to_string();
+
+        let mut response = call.execute(request).await?;
+        if !response.status().is_success() {
+            return Err(
+                ReqwestBackoffError::unexpected_status(response, "starting a download").await,
+            );
+        }
+        let validator = Validator::from_headers(response.headers());
+        // Where the body starts in the resource, and the last byte asked for
+        let (start, end) = match requested {
+            Some(range) if response.status() == StatusCode::PARTIAL_CONTENT => range,
+            _ => (0, None),
+        };
+        let mut received: u64 = 0;
+        loop {
+            let error = loop {
+                match response.chunk().await {
+                    Ok(Some(chunk)) => {
+                        writer.write_all(&chunk).await?;
+                        received += chunk.len() as u64;
+                    }
+                    Ok(None) => {
+                        writer.flush().await?;
+                        return Ok(received);
+                    }
+                    Err(error) => break error,
+                }
+            };
+            let Some(template) = template.as_ref() else {
+                warn!("Download was interrupted, but the request can't be cloned to resume it");
+                return Err(error.into());
+            };
+            if !is_interrupted_body(&error) {
+                return Err(error.into());
+            }
+            info!(received, "Download was interrupted");
+            call.wait(Err(error)).await?;
+
+            let mut request = template
+                .try_clone()
+                .expect("the template was cloned before");
+            if received > 0 {
+                let headers = request.headers_mut();
+                let range = match end {
+                    Some(end) => format!("bytes={}-{}", start + received, end),
+                    None => format!("bytes={}-", start + received),
+                };
+                headers.insert(
+                    RANGE,
+                    HeaderValue::try_from(range).expect("valid header value"),
+                );
+                if let Some(validator) = &validator {
+                    headers.insert(IF_RANGE, validator.value().clone());
+                }
+            }
+            response = call.execute(request).await?;
+            match response.status() {
+                StatusCode::PARTIAL_CONTENT if received > 0 => {
+                    if !validator
+                        .as_ref()
+                        .is_none_or(|v| v.matches(response.headers()))
+                    {
+                        return Err(ReqwestBackoffError::ResourceChanged { url, received });
+                    }
+                    check_range_start(&response, start + received)?;
+                }
+                status if status.is_success() && received == 0 => {}
+                // With If-Range, a full response means the validator no longer matches
+                StatusCode::OK if validator.is_some() => {
+                    return Err(ReqwestBackoffError::ResourceChanged { url, received });
+                }
+                // Without a validator, a full response means the server doesn't support ranges
+                StatusCode::OK => {
+                    return Err(ReqwestBackoffError::RangeNotSupported { url, received });
+                }
+                _ => {
+                    return Err(ReqwestBackoffError::unexpected_status(
+                        response,
+                        "resuming a download",
+                    )
+                    .await);
+                }
+            }
+        }
+    }
+
+    /// Downloads the body of `request` into a new file at `path`, see
+    /// [`download_with_backoff`](Self::download_with_backoff).
+    pub async fn download_to_file(&self, request: Request, path: impl AsRef<Path>) -> Result<u64> {
+        let mut file = tokio::fs::File::create(path).await?;
+        let written = self.download_with_backoff(request, &mut file).await?;
+        file.sync_all().await?;
+        Ok(written)
+    }
+}
+
+/// The start and the optional end of the `Range` in `request`, if it has one.
+fn requested_range(request: &Request) -> Result<Option<(u64, Option<u64>)>> {
+    let Some(range) = request.headers().get(RANGE) else {
+        return Ok(None);
+    };
+    let parsed = range
+        .to_str()
+        .ok()
+        .and_then(|range| range.strip_prefix("bytes="))
+        .and_then(|range| range.split_once('-'))
+        .and_then(|(start, end)| {
+            let start = start.trim().parse::<u64>().ok()?;
+            match end.trim() {
+                "" => Some((start, None)),
+                end => end.parse::<u64>().ok().map(|end| (start, Some(end))),
+            }
+        });
+    match parsed {
+        Some(range) => Ok(Some(range)),
+        None => Err(ReqwestBackoffError::InvalidHeader {
+            name: RANGE.to_string(),
+            value: String::from_utf8_lossy(range.as_bytes()).into_owned(),
+            reason: "only a single range with a start can be resumed".to_string(),
+        }),
+    }
+}
+
+/// Makes sure a `206` response continues at `offset`, from `Content-Range: bytes <start>-...`.
+fn check_range_start(response: &Response, offset: u64) -> Result<()> {
+    let Some(range) = response.headers().get(CONTENT_RANGE) else {
+        return Ok(());
+    };
+    let start = range
+        .to_str()
+        .ok()
+        .and_then(|range| range.strip_prefix("bytes "))
+        .and_then(|range| range.split_once('-'))
+        .and_then(|(start, _)| start.parse::<u64>().ok());
+    if start == Some(offset) {
+        return Ok(());
+    }
+    Err(ReqwestBackoffError::InvalidHeader {
+        name: CONTENT_RANGE.to_string(),
+        value: String::from_utf8_lossy(range.as_bytes()).into_owned(),
+        reason: format!("expected the range to start at {}", offset),
+    })
+}
@@ -0,0 +1,198 @@
+use std::time::Duration;
+
+use reqwest::header::HeaderMap;
+use reqwest::{Response, StatusCode};
+
+use crate::google::GoogleError;
+use crate::prelude::*;
+
+/// How many bytes of the body are kept in a [`ResponseSummary`].
+const BODY_SNIPPET_LIMIT: usize = 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReqwestBackoffError {
+    #[error("Reqwest error")]
+    Reqwest(#[from] reqwest::Error),
+    #[error("IO error")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to build the request: {0}")]
+    Build(#[source] reqwest::Error),
+    #[error("Other error")]
+    Other(#[from] Box<dyn StdError + Send + Sync>),
+    #[error("Backoff error after {backoff_attempts} attempts")]
+    BackoffExceeded {
+        backoff_attempts: u32,
+        elapsed: Duration,
+        history: Vec<AttemptRecord>,
+        /// The response of the last attempt, if it got one.
+        last_response: Option<Box<ResponseSummary>>,
+        /// The error of the last attempt, if it failed without a response.
+        #[source]
+        last_error: Option<reqwest::Error>,
+    },
+    #[error("Retry deadline exceeded after {attempts} attempts in {elapsed:?}")]
+    DeadlineExceeded {
+        elapsed: Duration,
+        attempts: u32,
+        history: Vec<AttemptRecord>,
+        /// The response of the last attempt, if it got one.
+        last_response: Option<Box<ResponseSummary>>,
+        /// The error of the last attempt, if it failed without a response.
+        #[source]
+        last_error: Option<reqwest::Error>,
+    },
+    #[error("Attempt #{attempts} timed out after {timeout:?} ({elapsed:?} elapsed in total)")]
+    AttemptTimeout {
+        timeout: Duration,
+        elapsed: Duration,
+        attempts: u32,
+        history: Vec<AttemptRecord>,
+    },
+    #[error("Retrying was aborted after {attempts} attempts")]
+    Aborted {
+        attempts: u32,
+        elapsed: Duration,
+        history: Vec<AttemptRecord>,
+    },
+    #[error("The circuit breaker for {host} is open")]
+    CircuitOpen {
+        host: String,
+        /// How long until the breaker lets trial requests through, if it is not already doing so.
+        retry_after: Option<Duration>,
+    },
+    #[error("Unexpected status {status} while {context}")]
+    UnexpectedStatus {
+        status: StatusCode,
+        context: &'static str,
+        response: Box<ResponseSummary>,
+    },
+    #[error("Failed to decode the {status} response body: {source}")]
+    Decode {
+        status: StatusCode,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("The resource at {url} changed after {received} bytes were downloaded")]
+    ResourceChanged { url: String, received: u64 },
+    #[error("The server for {url} ignored the range to resume after {received} bytes")]
+    RangeNotSupported { url: String, received: u64 },
+    #[error("Invalid {name} header {value:?}: {reason}")]
+    InvalidHeader {
+        name: String,
+        value: String,
+        reason: String,
+    },
+    #[error("Invalid host pattern: {0}")]
+    InvalidHostPattern(String),
+    #[error("Google API error {status}: {reason}")]
+    GoogleApi {
+        status: StatusCode,
+        reason: String,
+        error: GoogleError,
+    },
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(String),
+}
+
+impl ReqwestBackoffError {
+    /// The attempts made before giving up, if the error was caused by giving up.
+    pub fn history(&self) -> Option<&[AttemptRecord]> {
+        match self {
+            ReqwestBackoffError::BackoffExceeded { history, .. }
+            | ReqwestBackoffError::DeadlineExceeded { history, .. }
+            | ReqwestBackoffError::AttemptTimeout { history, .. }
+            | ReqwestBackoffError::Aborted { history, .. } => Some(history),
+            _ => None,
+        }
+    }
+
+    /// The response of the last attempt, if the error was caused by giving up after a response.
+    pub fn last_response(&self) -> Option<&ResponseSummary> {
+        match self {
+            ReqwestBackoffError::BackoffExceeded { last_response, .. }
+            | ReqwestBackoffError::DeadlineExceeded { last_response, .. } => {
+                last_response.as_deref()
+            }
+            _ => None,
+        }
+    }
+}
+
+impl ReqwestBackoffError {
+    /// The outcome of the last attempt of a call that failed with this error.
+    pub(crate) fn final_outcome(&self) -> AttemptOutcome {
+        match self {
+            ReqwestBackoffError::GoogleApi { status, .. } => AttemptOutcome::Status(*status),
+            ReqwestBackoffError::Reqwest(error) => AttemptOutcome::Error(error.to_string()),
+            _ => self
+                .history()
+                .and_then(|history| history.last())
+                .map(|record| record.outcome.clone())
+                .unwrap_or_else(|| AttemptOutcome::Error(self.to_string())),
+        }
+    }
+
+    pub(crate) async fn unexpected_status(response: Response, context: &'static str) -> Self {
+        ReqwestBackoffError::UnexpectedStatus {
+            status: response.status(),
+            context,
+            response: Box::new(ResponseSummary::from_response(response).await),
+        }
+    }
+}
+
+/// The result of a single attempt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttemptOutcome {
+    /// The server responded with this status.
+    Status(StatusCode),
+    /// The request failed without a response.
+    Error(String),
+}
+
+/// What happened during a single attempt of a call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttemptRecord {
+    /// The number of the attempt, starting at 1.
+    pub attempt: u32,
+    pub outcome: AttemptOutcome,
+    /// The time since the call started when the attempt finished.
+    pub elapsed: Duration,
+    /// The delay before the next attempt, or `None` if there was no next attempt.
+    pub delay: Option<Duration>,
+}
+
+/// The parts of a response that are kept after giving up on a request.
+#[derive(Debug, Clone)]
+pub struct ResponseSummary {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    /// The start of the body, lossily decoded as UTF-8.
+    pub body_snippet: String,
+    /// `true` if the body was longer than the snippet.
+    pub truncated: bool,
+}
+
+impl ResponseSummary {
+    /// Reads up to the first kilobyte of the body. Errors while reading it are ignored.
+    pub async fn from_response(mut response: Response) -> Self {
+        let status = response.status();
+        let headers = std::mem::take(response.headers_mut());
+        let mut body = Vec::new();
+        let mut truncated = false;
+        while let Ok(Some(chunk)) = response.chunk().await {
+            body.extend_from_slice(&chunk);
+            if body.len() > BODY_SNIPPET_LIMIT {
+                body.truncate(BODY_SNIPPET_LIMIT);
+                truncated = true;
+                break;
+            }
+        }
+        Self {
+            status,
+            headers,
+            body_snippet: String::from_utf8_lossy(&body).into_owned(),
+            truncated,
+        }
+    }
+}
@@ -0,0 +1,69 @@
+//! Helpers that read the whole response body, retrying when the connection drops while reading it.
+
+use bytes::Bytes;
+use reqwest::{Request, StatusCode};
+use serde::de::DeserializeOwned;
+
+use crate::prelude::*;
+use crate::resume::{is_interrupted_body, ResumableCall};
+use crate::ReqwestClient;
+
+impl ReqwestClient {
+    /// Sends `request` with backoff and reads the whole body.
+    ///
+    /// [`execute_with_backoff`](Self::execute_with_backoff) returns as soon as the headers
+    /// arrive, so a connection that drops while the body is read isn't retried. Here, the request
+    /// and the body are one unit: if reading the body fails with an I/O error or a timeout, the
+    /// request is sent again, delayed by the policy for the host like transport errors. All
+    /// attempts share the policy's attempts and the maximum elapsed time, and the
+    /// [`RequestOverrides`](crate::RequestOverrides) in the extensions of the request apply to all
+    /// of them. Responses that are not successful fail with
+    /// [`ReqwestBackoffError::UnexpectedStatus`].
+    #[tracing::instrument(skip(self))]
+    pub async fn get_bytes_with_backoff(&self, request: Request) -> Result<Bytes> {
+        self.fetch_body(request).await.map(|(_, body)| body)
+    }
+
+    /// Like [`get_bytes_with_backoff`](Self::get_bytes_with_backoff), and deserializes the body
+    /// as JSON.
+    ///
+    /// A body that isn't valid JSON for `T` fails with [`ReqwestBackoffError::Decode`] without
+    /// retrying.
+    #[tracing::instrument(skip(self))]
+    pub async fn get_json_with_backoff<T: DeserializeOwned>(&self, request: Request) -> Result<T> {
+        let (status, body) = self.fetch_body(request).await?;
+        serde_json::from_slice(&body)
+            .map_err(|source| ReqwestBackoffError::Decode { status, source })
+    }
+
+    async fn fetch_body(&self, request: Request) -> Result<(StatusCode, Bytes)> {
+        let (mut call, mut request) = ResumableCall::with_overrides(self, request)?;
+        let template = request.try_clone();
+        loop {
+            let response = call.execute(request).await?;
+            let status = response.status();
+            if !status.is_success() {
+                return Err(ReqwestBackoffError::unexpected_status(
+                    response,
+                    "reading a response body",
+                )
+                .await);
+            }
+            let error = match response.bytes().await {
+                Ok(body) => return Ok((status, body)),
+                Err(error) => error,
+            };
+            let Some(template) = template.as_ref() else {
+                warn!("Reading the body failed, but the request can't be cloned to retry it");
+                return Err(error.into());
+            };
+            if !is_interrupted_body(&error) {
+                return Err(error.into());
+            }
+            call.wait(Err(error)).await?;
+            request = template
+                .try_clone()
+                .expect("the template was cloned before");
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use reqwest::StatusCode;
+use serde::Deserialize;
+
+/// Reasons in Google API errors that mean the request can succeed if it is retried later.
+pub const RETRYABLE_REASONS: &[&str] = &[
+    "rateLimitExceeded",
+    "userRateLimitExceeded",
+    "backendError",
+    "internalError",
+    "servingLimitExceeded",
+];
+
+/// Reasons in Google API errors that mean the request was rejected because of a rate limit,
+/// before the API acted on it.
+pub const RATE_LIMIT_REASONS: &[&str] = &["rateLimitExceeded", "userRateLimitExceeded"];
+
+/// A single entry of `error.errors` in a Google API error response.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct GoogleErrorItem {
+    #[serde(default)]
+    pub domain: String,
+    #[serde(default)]
+    pub reason: String,
+    #[serde(default)]
+    pub message: String,
+}
+
+/// The `error` object of a Google API error response.
+///
+/// See <https://cloud.google.com/apis/design/errors>.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct GoogleError {
+    #[serde(default)]
+    pub code: u16,
+    #[serde(default)]
+    pub message: String,
+    #[serde(default)]
+    pub errors: Vec<GoogleErrorItem>,
+}
+
+#[derive(Deserialize)]
+struct GoogleErrorBody {
+    error: GoogleError,
+}
+
+impl GoogleError {
+    /// Parses the body of an error response. Returns `None` if it is not a Google API error.
+    pub fn from_body(body: &[u8]) -> Option<Self> {
+        serde_json::from_slice::<GoogleErrorBody>(body)
+            .ok()
+            .map(|body| body.error)
+    }
+
+    /// The first reason given for the error, if any.
+    pub fn reason(&self) -> Option<&str> {
+        self.errors
+            .iter()
+            .map(|item| item.reason.as_str())
+            .find(|reason| !reason.is_empty())
+    }
+}
+
+/// Returns `true` for the statuses Google uses for rate limit errors, which need the body to tell
+/// them apart from permanent failures.
+pub(crate) fn is_ambiguous_status(status: StatusCode) -> bool {
+    status == StatusCode::FORBIDDEN || status == StatusCode::BAD_REQUEST
+}
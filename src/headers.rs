@@ -0,0 +1,148 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{Response, StatusCode};
+
+use crate::config::BackoffConfig;
+use crate::prelude::*;
+
+/// Parses the `Retry-After` header, which is either a number of seconds or an HTTP-date.
+///
+/// A date in the past results in a zero duration. Returns `None` if the header is missing.
+pub fn parse_retry_after(headers: &HeaderMap, now: DateTime<Utc>) -> Result<Option<Duration>> {
+    let Some(value) = header_str(headers, RETRY_AFTER.as_str())? else {
+        return Ok(None);
+    };
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Ok(Some(Duration::from_secs(seconds)));
+    }
+    let date = DateTime::parse_from_rfc2822(value)
+        .map_err(|e| invalid_header(RETRY_AFTER.as_str(), value, e))?
+        .with_timezone(&Utc);
+    Ok(Some((date - now).to_std().unwrap_or(Duration::ZERO)))
+}
+
+/// Returns `true` for the statuses on which servers send `Retry-After` to signal a retry.
+pub(crate) fn is_retry_after_status(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::SERVICE_UNAVAILABLE
+}
+
+/// The delay requested by the `Retry-After` header of a `429` or `503` response, clamped to
+/// [`BackoffConfig::max_retry_after`].
+#[tracing::instrument(skip(response, config), fields(status = %response.status()))]
+pub(crate) fn retry_after_time(
+    response: &Response,
+    config: &BackoffConfig,
+    now: DateTime<Utc>,
+) -> Option<Duration> {
+    if !is_retry_after_status(response.status()) {
+        return None;
+    }
+    let requested = match parse_retry_after(response.headers(), now) {
+        Ok(requested) => requested?,
+        Err(e) => {
+            warn!("Ignoring Retry-After: {}", e);
+            return None;
+        }
+    };
+    let wait = requested.min(config.max_retry_after);
+    info!(
+        ?requested,
+        ?wait,
+        "Server requested a delay with Retry-After"
+    );
+    Some(wait)
+}
+
+/// The state of a Twitch rate limit bucket, as reported by the `Ratelimit-*` headers.
+///
+/// See <https://dev.twitch.tv/docs/api/guide/#twitch-rate-limits>.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TwitchRateLimit {
+    /// The number of points the bucket holds (`Ratelimit-Limit`).
+    pub limit: Option<u32>,
+    /// The number of points left in the bucket (`Ratelimit-Remaining`).
+    pub remaining: Option<u32>,
+    /// When the bucket is refilled (`Ratelimit-Reset`).
+    pub reset: Option<DateTime<Utc>>,
+}
+
+impl TwitchRateLimit {
+    /// Reads the `Ratelimit-*` headers. Returns `None` if none of them are present.
+    pub fn from_headers(headers: &HeaderMap) -> Result<Option<Self>> {
+        let reset = match parse_header::<i64>(headers, "Ratelimit-Reset")? {
+            Some(timestamp) => Some(DateTime::from_timestamp(timestamp, 0).ok_or_else(|| {
+                invalid_header(
+                    "Ratelimit-Reset",
+                    &timestamp.to_string(),
+                    "timestamp out of range",
+                )
+            })?),
+            None => None,
+        };
+        let rate_limit = Self {
+            limit: parse_header(headers, "Ratelimit-Limit")?,
+            remaining: parse_header(headers, "Ratelimit-Remaining")?,
+            reset,
+        };
+        if rate_limit.limit.is_none()
+            && rate_limit.remaining.is_none()
+            && rate_limit.reset.is_none()
+        {
+            Ok(None)
+        } else {
+            Ok(Some(rate_limit))
+        }
+    }
+
+    /// Takes a point for a new request and returns how long to wait if only `reserve` points are
+    /// left until the bucket resets.
+    pub(crate) fn acquire(&mut self, reserve: u32, now: DateTime<Utc>) -> Option<Duration> {
+        let reset = self.reset?;
+        if reset <= now {
+            // The bucket has been refilled since we last heard from the server
+            self.remaining = self.limit;
+            self.reset = None;
+            return None;
+        }
+        let remaining = self.remaining?;
+        if remaining > reserve {
+            self.remaining = Some(remaining - 1);
+            return None;
+        }
+        Some((reset - now).to_std().unwrap_or(Duration::ZERO))
+    }
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Result<Option<&'a str>> {
+    let Some(value) = headers.get(name) else {
+        return Ok(None);
+    };
+    let value = value
+        .to_str()
+        .map_err(|e| invalid_header(name, &String::from_utf8_lossy(value.as_bytes()), e))?;
+    Ok(Some(value.trim()))
+}
+
+fn parse_header<T>(headers: &HeaderMap, name: &str) -> Result<Option<T>>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    match header_str(headers, name)? {
+        Some(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|e| invalid_header(name, value, e)),
+        None => Ok(None),
+    }
+}
+
+fn invalid_header(name: &str, value: &str, reason: impl std::fmt::Display) -> ReqwestBackoffError {
+    ReqwestBackoffError::InvalidHeader {
+        name: name.to_string(),
+        value: value.to_string(),
+        reason: reason.to_string(),
+    }
+}
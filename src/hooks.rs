@@ -0,0 +1,102 @@
+use std::fmt::{Debug, Formatter};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use url::Url;
+
+use crate::clock::BoxFuture;
+use crate::error::AttemptOutcome;
+use crate::policy::BackoffPolicy;
+
+/// What a hook is told about an attempt.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct RetryEvent {
+    pub url: Url,
+    /// The number of the attempt, starting at 1.
+    pub attempt: u32,
+    pub outcome: AttemptOutcome,
+    /// The delay before the next attempt. Only set for [`on_retry`](crate::ReqwestClientBuilder::on_retry).
+    pub delay: Option<Duration>,
+    /// The time since the call started.
+    pub elapsed: Duration,
+    /// The policy used for the request.
+    pub policy: Arc<dyn BackoffPolicy>,
+}
+
+/// Returned by the [`on_retry`](crate::ReqwestClientBuilder::on_retry) hook to decide if the
+/// retry should happen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RetryAction {
+    #[default]
+    Continue,
+    /// Stop retrying and fail with [`ReqwestBackoffError::Aborted`](crate::ReqwestBackoffError::Aborted).
+    Abort,
+}
+
+type RetryHook = Arc<dyn Fn(RetryEvent) -> BoxFuture<'static, RetryAction> + Send + Sync>;
+type EventHook = Arc<dyn Fn(RetryEvent) -> BoxFuture<'static, ()> + Send + Sync>;
+
+/// The callbacks registered on a client.
+#[derive(Clone, Default)]
+pub(crate) struct Hooks {
+    on_retry: Option<RetryHook>,
+    on_give_up: Option<EventHook>,
+    on_success: Option<EventHook>,
+}
+
+impl Debug for Hooks {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Hooks")
+            .field("on_retry", &self.on_retry.is_some())
+            .field("on_give_up", &self.on_give_up.is_some())
+            .field("on_success", &self.on_success.is_some())
+            .finish()
+    }
+}
+
+impl Hooks {
+    pub(crate) fn set_on_retry<F, Fut>(&mut self, hook: F)
+    where
+        F: Fn(RetryEvent) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = RetryAction> + Send + 'static,
+    {
+        self.on_retry = Some(Arc::new(move |event| Box::pin(hook(event))));
+    }
+
+    pub(crate) fn set_on_give_up<F, Fut>(&mut self, hook: F)
+    where
+        F: Fn(RetryEvent) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.on_give_up = Some(Arc::new(move |event| Box::pin(hook(event))));
+    }
+
+    pub(crate) fn set_on_success<F, Fut>(&mut self, hook: F)
+    where
+        F: Fn(RetryEvent) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.on_success = Some(Arc::new(move |event| Box::pin(hook(event))));
+    }
+
+    pub(crate) async fn on_retry(&self, event: RetryEvent) -> RetryAction {
+        match &self.on_retry {
+            Some(hook) => hook(event).await,
+            None => RetryAction::Continue,
+        }
+    }
+
+    pub(crate) async fn on_give_up(&self, event: RetryEvent) {
+        if let Some(hook) = &self.on_give_up {
+            hook(event).await;
+        }
+    }
+
+    pub(crate) async fn on_success(&self, event: RetryEvent) {
+        if let Some(hook) = &self.on_success {
+            hook(event).await;
+        }
+    }
+}
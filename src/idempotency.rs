@@ -0,0 +1,97 @@
+//! Retry rules for HTTP methods that are not idempotent.
+//!
+//! Retrying a `POST` or `PATCH` after the server received it may apply it twice. So by default,
+//! requests with these methods are only retried if the failure shows that the server didn't act on
+//! them: the connection could not be established, or the server rejected the request with `429`
+//! or a Google API error with a [rate limit reason](crate::google::RATE_LIMIT_REASONS).
+
+use rand::Rng;
+use reqwest::header::{HeaderName, HeaderValue};
+use reqwest::{Method, Request, Response, StatusCode};
+
+use crate::google::{GoogleError, RATE_LIMIT_REASONS};
+use crate::prelude::*;
+
+/// The header used to make unsafe requests idempotent, see
+/// <https://datatracker.ietf.org/doc/draft-ietf-httpapi-idempotency-key-header/>.
+pub const IDEMPOTENCY_KEY: HeaderName = HeaderName::from_static("idempotency-key");
+
+/// How requests with methods that are not idempotent, like `POST` and `PATCH`, are retried.
+///
+/// Requests that already carry an `Idempotency-Key` header are always retried like safe ones.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UnsafeRetries {
+    /// Only retry failures that happened before the server could act on the request.
+    #[default]
+    BeforeSent,
+    /// Add a generated `Idempotency-Key` header, which is kept across attempts, to requests that
+    /// don't have one, and retry them like safe ones.
+    IdempotencyKey,
+    /// Retry like safe methods. Only use this if the API deduplicates requests itself.
+    Always,
+}
+
+/// Returns `true` for methods that can be repeated without changing the result (RFC 9110).
+pub fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::PUT | Method::DELETE | Method::OPTIONS | Method::TRACE
+    )
+}
+
+/// Adds an `Idempotency-Key` if configured and returns whether `request` may be retried after the
+/// server received it.
+pub(crate) fn prepare(request: &mut Request, unsafe_retries: UnsafeRetries) -> bool {
+    if is_idempotent(request.method()) || request.headers().contains_key(IDEMPOTENCY_KEY) {
+        return true;
+    }
+    match unsafe_retries {
+        UnsafeRetries::BeforeSent => false,
+        UnsafeRetries::IdempotencyKey => {
+            let key = generate_key();
+            info!(
+                "Sending {} request with idempotency key {}",
+                request.method(),
+                key
+            );
+            let value = HeaderValue::try_from(key).expect("the key is a valid header value");
+            request.headers_mut().insert(IDEMPOTENCY_KEY, value);
+            true
+        }
+        UnsafeRetries::Always => true,
+    }
+}
+
+/// Returns `true` if the retryable attempt failed in a way that shows the server didn't act on the
+/// request. `body` is the body of the response, if the policy read it.
+pub(crate) fn failed_before_processing(
+    result: &reqwest::Result<Response>,
+    body: Option<&[u8]>,
+) -> bool {
+    match result {
+        Ok(response) if response.status() == StatusCode::TOO_MANY_REQUESTS => true,
+        // Other reasons, like `backendError`, don't show that the request wasn't processed
+        Ok(_) => body.and_then(GoogleError::from_body).is_some_and(|error| {
+            error
+                .reason()
+                .is_some_and(|reason| RATE_LIMIT_REASONS.contains(&reason))
+        }),
+        Err(error) => error.is_connect(),
+    }
+}
+
+/// A random UUID (version 4).
+fn generate_key() -> String {
+    let mut bytes: [u8; 16] = rand::rng().random();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
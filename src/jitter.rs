@@ -0,0 +1,139 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// Randomizes computed delays, so clients that failed at the same time don't retry in lockstep.
+///
+/// `delay` is the exponential delay for the attempt, see
+/// <https://aws.amazon.com/blogs/architecture/exponential-backoff-and-jitter/>.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Jitter {
+    /// Use the delay as is.
+    #[default]
+    None,
+    /// A random delay between zero and `delay`.
+    Full,
+    /// Half of `delay` plus a random delay of up to the other half.
+    Equal,
+    /// A random delay between the base delay and three times the previous delay.
+    Decorrelated,
+}
+
+/// The random number generator used for jitter. Clones share the same generator.
+#[derive(Debug, Clone)]
+pub(crate) struct JitterRng(Arc<Mutex<StdRng>>);
+
+impl JitterRng {
+    /// A generator with the given seed, or a randomly seeded one if `seed` is `None`.
+    pub(crate) fn new(seed: Option<u64>) -> Self {
+        let rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_os_rng(),
+        };
+        Self(Arc::new(Mutex::new(rng)))
+    }
+
+    /// A random duration in `min..=max`.
+    fn between(&self, min: Duration, max: Duration) -> Duration {
+        if min >= max {
+            return min;
+        }
+        let secs = self
+            .0
+            .lock()
+            .unwrap()
+            .random_range(min.as_secs_f64()..=max.as_secs_f64());
+        Duration::from_secs_f64(secs)
+    }
+}
+
+impl Jitter {
+    pub(crate) fn apply(
+        &self,
+        delay: Duration,
+        base_delay: Duration,
+        max_delay: Duration,
+        previous_delay: Option<Duration>,
+        rng: &JitterRng,
+    ) -> Duration {
+        match self {
+            Jitter::None => delay,
+            Jitter::Full => rng.between(Duration::ZERO, delay),
+            Jitter::Equal => delay / 2 + rng.between(Duration::ZERO, delay / 2),
+            Jitter::Decorrelated => {
+                let previous = previous_delay.unwrap_or(base_delay).max(base_delay);
+                rng.between(base_delay, previous.saturating_mul(3))
+                    .min(max_delay)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: Duration = Duration::from_secs(1);
+    const MAX: Duration = Duration::from_secs(60);
+
+    fn delays(jitter: Jitter, seed: u64) -> Vec<Duration> {
+        let rng = JitterRng::new(Some(seed));
+        let mut previous = None;
+        (0..100)
+            .map(|_| {
+                let delay = jitter.apply(Duration::from_secs(8), BASE, MAX, previous, &rng);
+                previous = Some(delay);
+                delay
+            })
+            .collect()
+    }
+
+    #[test]
+    fn none_keeps_the_delay() {
+        assert!(delays(Jitter::None, 1)
+            .iter()
+            .all(|delay| *delay == Duration::from_secs(8)));
+    }
+
+    #[test]
+    fn full_is_between_zero_and_the_delay() {
+        let delays = delays(Jitter::Full, 1);
+        assert!(delays.iter().all(|delay| *delay <= Duration::from_secs(8)));
+        assert!(delays.iter().any(|delay| *delay < Duration::from_secs(4)));
+    }
+
+    #[test]
+    fn equal_is_between_half_the_delay_and_the_delay() {
+        let delays = delays(Jitter::Equal, 1);
+        assert!(delays
+            .iter()
+            .all(|delay| (Duration::from_secs(4)..=Duration::from_secs(8)).contains(delay)));
+        assert!(delays.windows(2).any(|pair| pair[0] != pair[1]));
+    }
+
+    #[test]
+    fn decorrelated_is_between_the_base_and_three_times_the_previous_delay() {
+        let delays = delays(Jitter::Decorrelated, 1);
+        assert!((BASE..=BASE * 3).contains(&delays[0]));
+        for pair in delays.windows(2) {
+            let upper = (pair[0] * 3).min(MAX);
+            assert!(
+                (BASE..=upper).contains(&pair[1]),
+                "{:?} after {:?}",
+                pair[1],
+                pair[0]
+            );
+        }
+        assert!(delays.iter().any(|delay| *delay > BASE * 3));
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_delays() {
+        for jitter in [Jitter::Full, Jitter::Equal, Jitter::Decorrelated] {
+            assert_eq!(delays(jitter, 42), delays(jitter, 42));
+            assert_ne!(delays(jitter, 42), delays(jitter, 43));
+        }
+    }
+}
@@ -1,33 +1,92 @@
 use std::ops::Deref;
+use std::sync::Arc;
+use std::time::Duration;
 
+use bytes::Bytes;
 use chrono::{DateTime, Utc};
-use reqwest::{Error, Request, Response};
-use url::Host;
+use reqwest::{Request, Response, ResponseBuilderExt, StatusCode};
+use url::Url;
 
+use auth::{set_bearer_token, TokenProviders};
+use body::RequestSource;
+use circuit::CircuitBreakers;
+use clock::{BoxFuture, Clock, Sleeper, SystemClock, TokioSleeper};
+use hooks::Hooks;
 use prelude::*;
+use rate_limit::RateLimiter;
+use request::take_overrides;
 
-const MAX_BACKOFF_ATTEMPTS: u32 = 50;
-const MAX_BACKOFF_ATTEMPTS_GOOGLE: u32 = 50;
-const MAX_BACKOFF_ATTEMPTS_TWITCH: u32 = 50;
-
-const GOOGLE_BASE_BACKOFF_TIME_S: u64 = 2;
-const GOOGLE_MAX_BACKOFF_TIME_S: u64 = 3600;
+pub use auth::TokenProvider;
+pub use body::BodySource;
+pub use builder::ReqwestClientBuilder;
+pub use circuit::{CircuitBreakerConfig, CircuitState};
+pub use config::{BackoffConfig, TransportErrors};
+pub use error::{AttemptOutcome, AttemptRecord, ReqwestBackoffError, ResponseSummary};
+pub use hooks::{RetryAction, RetryEvent};
+pub use idempotency::UnsafeRetries;
+pub use jitter::Jitter;
+pub use policy::{BackoffPolicy, DefaultPolicy, GooglePolicy, RetryState, TwitchPolicy};
+pub use rate_limit::RateLimit;
+pub use registry::{HostPattern, PolicyRegistry};
+pub use request::{BackoffRequestBuilder, RequestBuilderExt, RequestOverrides};
+pub use upload::{ResumableUpload, UploadStatus};
 
+pub mod auth;
+pub mod body;
+pub mod builder;
+pub mod circuit;
+pub mod clock;
+pub mod config;
+pub mod download;
+pub mod error;
+pub mod fetch;
+pub mod google;
+pub mod headers;
+pub mod hooks;
+pub mod idempotency;
+pub mod jitter;
+#[cfg(feature = "middleware")]
+pub mod middleware;
+pub mod policy;
 pub mod prelude;
+pub mod rate_limit;
+pub mod registry;
+pub mod request;
+mod resume;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(feature = "tower")]
+pub mod tower;
+pub mod upload;
+
+/// Sends a single attempt of a request.
+type SendFn<'a> =
+    dyn Fn(Request) -> BoxFuture<'a, Result<reqwest::Result<Response>>> + Send + Sync + 'a;
 
-#[derive(Debug, thiserror::Error)]
-pub enum ReqwestBackoffError {
-    #[error("Reqwest error")]
-    Reqwest(#[from] Error),
-    #[error("Other error")]
-    Other(#[from] Box<dyn StdError + Send + Sync>),
-    #[error("Backoff error after {backoff_attempts} attempts")]
-    BackoffExceeded { backoff_attempts: u32 },
+/// The attempts and the time shared by all requests of a call.
+#[derive(Debug)]
+struct CallState {
+    /// When the call started.
+    start: DateTime<Utc>,
+    /// The deadline of the request, or else the maximum elapsed time of the client.
+    max_elapsed: Option<Duration>,
+    /// The number of the current attempt, counted across all requests of the call.
+    attempt: u32,
 }
 
 #[derive(Debug, Clone)]
 pub struct ReqwestClient {
     client: reqwest::Client,
+    registry: PolicyRegistry,
+    max_elapsed: Option<Duration>,
+    attempt_timeout: Option<Duration>,
+    rate_limiter: RateLimiter,
+    circuit_breakers: CircuitBreakers,
+    clock: Arc<dyn Clock>,
+    sleeper: Arc<dyn Sleeper>,
+    hooks: Hooks,
+    token_providers: TokenProviders,
+    unsafe_retries: UnsafeRetries,
 }
 
 impl Deref for ReqwestClient {
@@ -40,7 +99,19 @@ impl Deref for ReqwestClient {
 
 impl From<reqwest::Client> for ReqwestClient {
     fn from(client: reqwest::Client) -> Self {
-        Self { client }
+        Self {
+            client,
+            registry: PolicyRegistry::default(),
+            max_elapsed: None,
+            attempt_timeout: None,
+            rate_limiter: RateLimiter::default(),
+            circuit_breakers: CircuitBreakers::default(),
+            clock: Arc::new(SystemClock),
+            sleeper: Arc::new(TokioSleeper),
+            hooks: Hooks::default(),
+            token_providers: TokenProviders::default(),
+            unsafe_retries: UnsafeRetries::default(),
+        }
     }
 }
 
@@ -50,159 +121,434 @@ impl Default for ReqwestClient {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum HostType {
-    Twitch,
-    Google,
-    Youtube,
-    Other,
-}
-
 impl ReqwestClient {
     pub fn new() -> Self {
-        Self {
-            client: reqwest::Client::new(),
-        }
+        Self::from(reqwest::Client::new())
     }
+
+    pub fn builder() -> ReqwestClientBuilder {
+        ReqwestClientBuilder::new()
+    }
+
+    /// Use `policy` for all requests matching `pattern`, replacing any policy previously set for it.
+    pub fn with_policy(
+        mut self,
+        pattern: HostPattern,
+        policy: impl BackoffPolicy + 'static,
+    ) -> Self {
+        self.registry.insert(pattern, policy);
+        self
+    }
+
+    /// Use `policy` for all requests that match none of the registered patterns.
+    pub fn with_default_policy(mut self, policy: impl BackoffPolicy + 'static) -> Self {
+        self.registry.set_fallback(policy);
+        self
+    }
+
+    /// Replace the whole policy registry, including the built-in entries.
+    pub fn with_registry(mut self, registry: PolicyRegistry) -> Self {
+        self.registry = registry;
+        self
+    }
+
+    pub fn registry(&self) -> &PolicyRegistry {
+        &self.registry
+    }
+
+    /// The state of the circuit breaker for `url`, if one is configured for its host.
+    pub fn circuit_state(&self, url: &Url) -> Option<CircuitState> {
+        self.circuit_breakers.state(url)
+    }
+
+    /// Execute a request with backoff if the response or transport error indicates that it should.
+    ///
+    /// [`RequestOverrides`] in the extensions of the request are merged with the policy for its
+    /// host.
     #[tracing::instrument]
     pub async fn execute_with_backoff(&self, request: Request) -> Result<Response> {
-        let host: HostType = get_host_from_request(&request);
-
-        let request_clone = request.try_clone();
-        if let Some(request_clone) = request_clone {
-            self.execute_with_backoff_inner(request_clone, host).await
-        } else {
-            warn!("Failed to clone request. No backoff possible.");
-            Ok(self
-                .client
-                .execute(request)
-                .await
-                .map_err(ReqwestBackoffError::Reqwest)?)
+        let (request, overrides) = take_overrides(request)?;
+        self.execute_with_overrides(request, &overrides, &self.sender())
+            .await
+    }
+
+    /// Sends `request` with backoff, or once if it can't be cloned.
+    async fn execute_with_overrides(
+        &self,
+        request: Request,
+        overrides: &RequestOverrides,
+        send: &SendFn<'_>,
+    ) -> Result<Response> {
+        self.execute_with_backoff_inner(RequestSource::new(request), overrides, send)
+            .await
+    }
+
+    /// Like [`execute_with_backoff`](Self::execute_with_backoff), but creates the body for every
+    /// attempt from `body`, so requests with streamed bodies can be retried.
+    ///
+    /// Any body already set on `request` is ignored.
+    #[tracing::instrument]
+    pub async fn execute_with_body(&self, request: Request, body: BodySource) -> Result<Response> {
+        let (request, overrides) = take_overrides(request)?;
+        self.execute_with_backoff_inner(
+            RequestSource::with_body(request, body),
+            &overrides,
+            &self.sender(),
+        )
+        .await
+    }
+
+    /// Sends attempts with the wrapped [`reqwest::Client`].
+    fn sender(&self) -> impl Fn(Request) -> BoxFuture<'static, Result<reqwest::Result<Response>>> {
+        let client = self.client.clone();
+        move |request| {
+            let response = client.execute(request);
+            Box::pin(async move { Ok(response.await) })
         }
     }
 
-    /// Execute a request with backoff if the response indicates that it should.
+    /// Resolves the policy for the request and runs the attempts.
+    async fn execute_with_backoff_inner(
+        &self,
+        request: RequestSource,
+        overrides: &RequestOverrides,
+        send: &SendFn<'_>,
+    ) -> Result<Response> {
+        let policy = overrides.apply(self.get_policy_for_request(request.template()));
+        let mut state = self.start_call(overrides.deadline);
+        self.execute_attempts(request, &policy, send, &mut state)
+            .await
+    }
+
+    /// The state of a new call, with `deadline` replacing the maximum elapsed time of the client.
+    fn start_call(&self, deadline: Option<Duration>) -> CallState {
+        CallState {
+            start: self.clock.now(),
+            max_elapsed: deadline.or(self.max_elapsed),
+            attempt: 1,
+        }
+    }
+
+    /// Execute a request with backoff if the response or transport error indicates that it should.
     ///
     /// # Arguments  
     ///
     /// * `self` - The client to use for the request.
-    /// * `request` - The request to execute. This needs to be cloneable otherwise the function will panic. (not cloneable requests can't be retried)
-    /// * `host` - The host of the request. This is used to determine the backoff time.
-    async fn execute_with_backoff_inner(
+    /// * `request` - Creates the request for each attempt.
+    /// * `policy` - The policy for the request. This is used to determine the backoff time.
+    /// * `send` - Sends a single attempt. Errors other than [`reqwest::Error`]s are not retried.
+    /// * `state` - The attempts and the time used so far, updated to the last attempt. Calls made
+    ///   of several requests pass the same state to share the maximum number of attempts and the
+    ///   maximum elapsed time.
+    async fn execute_attempts(
         &self,
-        request: Request,
-        host: HostType,
+        mut request: RequestSource,
+        policy: &Arc<dyn BackoffPolicy>,
+        send: &SendFn<'_>,
+        state: &mut CallState,
     ) -> Result<Response> {
-        let mut attempt: u32 = 1;
-        let mut response = self
-            .execute(request.try_clone().unwrap())
-            .await
-            .map_err(ReqwestBackoffError::Reqwest)?;
-        while check_response_is_backoff(&response, host) {
-            if is_backoff_limit_reached(attempt, host) {
+        let idempotent = idempotency::prepare(request.template_mut(), self.unsafe_retries);
+        let result = self
+            .retry_loop(&request, policy, send, idempotent, state)
+            .await;
+        let event = |outcome| RetryEvent {
+            url: request.url().clone(),
+            attempt: state.attempt,
+            outcome,
+            delay: None,
+            elapsed: self.elapsed_since(state.start),
+            policy: policy.clone(),
+        };
+        match &result {
+            Ok(response) => {
+                self.hooks
+                    .on_success(event(AttemptOutcome::Status(response.status())))
+                    .await
+            }
+            Err(error) => self.hooks.on_give_up(event(error.final_outcome())).await,
+        }
+        result
+    }
+
+    async fn retry_loop(
+        &self,
+        request: &RequestSource,
+        policy: &Arc<dyn BackoffPolicy>,
+        send: &SendFn<'_>,
+        idempotent: bool,
+        call: &mut CallState,
+    ) -> Result<Response> {
+        let start = call.start;
+        let max_elapsed = call.max_elapsed;
+        let attempt = &mut call.attempt;
+        let elapsed = || self.elapsed_since(start);
+        let mut previous_delay: Option<Duration> = None;
+        let mut history: Vec<AttemptRecord> = Vec::new();
+        let token_provider = self.token_providers.get(request.url());
+        let mut token = match token_provider {
+            Some(provider) => Some(provider.token().await?),
+            None => None,
+        };
+        let mut refreshed_token = false;
+        loop {
+            if let Some(wait) = self.rate_limiter.acquire(request.url(), self.clock.now()) {
+                self.sleeper.sleep(wait).await;
+            }
+            if let Some(wait) = policy.delay_before_request(request.template(), self.clock.now()) {
+                self.sleeper.sleep(wait).await;
+            }
+            let mut attempt_request = request.next().await?;
+            if let Some(token) = &token {
+                set_bearer_token(&mut attempt_request, token)?;
+            }
+            // Taken right before sending, so waits and local errors don't hold a trial request
+            let permit = self.circuit_breakers.acquire(request.url(), &self.clock)?;
+            let send = send(attempt_request);
+            let result = match self.attempt_timeout(elapsed(), max_elapsed) {
+                None => send.await,
+                Some(timeout) => match clock::timeout(self.sleeper.as_ref(), timeout, send).await {
+                    Some(result) => result,
+                    None => {
+                        permit.record(true);
+                        return Err(timeout_error(
+                            elapsed(),
+                            max_elapsed,
+                            *attempt,
+                            timeout,
+                            history,
+                        ));
+                    }
+                },
+            };
+            let mut result = match result {
+                Ok(result) => result,
+                // Errors of the sender are not caused by the host
+                Err(error) => {
+                    permit.release();
+                    return Err(error);
+                }
+            };
+            if let Ok(response) = &result {
+                self.rate_limiter
+                    .observe(request.url(), response.headers(), self.clock.now());
+                policy.on_response(response);
+                if let Some(provider) = token_provider {
+                    if response.status() == StatusCode::UNAUTHORIZED
+                        && !refreshed_token
+                        && request.can_repeat()
+                    {
+                        info!("Access token was rejected, refreshing it");
+                        refreshed_token = true;
+                        permit.record(false);
+                        token = Some(provider.refresh().await?);
+                        continue;
+                    }
+                }
+            }
+            let mut checked_body = None;
+            let retry = match &mut result {
+                Ok(response) if !policy.should_backoff(response) => Ok(false),
+                Ok(response) if !policy.needs_body(response) => Ok(true),
+                Ok(response) => {
+                    check_response_body(response, policy.as_ref())
+                        .await
+                        .map(|(retry, body)| {
+                            checked_body = Some(body);
+                            retry
+                        })
+                }
+                Err(error) => {
+                    let retry = policy.should_retry_error(error);
+                    if retry {
+                        warn!("Request failed with a retryable error: {:?}", error);
+                    }
+                    Ok(retry)
+                }
+            };
+            // Responses that are not retried show that the host is up, even if they are errors
+            let failed = result.is_err() || matches!(retry, Ok(true));
+            permit.record(failed);
+            let mut retry = retry?;
+            if retry
+                && !idempotent
+                && !idempotency::failed_before_processing(&result, checked_body.as_deref())
+            {
+                info!(
+                    "Not retrying the {} request, the server may have acted on it already",
+                    request.template().method()
+                );
+                retry = false;
+            }
+            if retry && !request.can_repeat() {
+                warn!("Not retrying the request, it can't be cloned");
+                retry = false;
+            }
+            if !retry {
+                return result.map_err(ReqwestBackoffError::Reqwest);
+            }
+            // Don't wait for the next attempt if this failure opened the circuit breaker
+            self.circuit_breakers
+                .check(request.url(), self.clock.now())?;
+            let mut record = AttemptRecord {
+                attempt: *attempt,
+                outcome: match &result {
+                    Ok(response) => AttemptOutcome::Status(response.status()),
+                    Err(error) => AttemptOutcome::Error(error.to_string()),
+                },
+                elapsed: elapsed(),
+                delay: None,
+            };
+            if *attempt >= policy.max_attempts() {
+                history.push(record);
+                let (last_response, last_error) = summarize_result(result).await;
                 return Err(ReqwestBackoffError::BackoffExceeded {
-                    backoff_attempts: attempt,
+                    backoff_attempts: *attempt,
+                    elapsed: elapsed(),
+                    history,
+                    last_response,
+                    last_error,
+                });
+            }
+            let state = RetryState::new(*attempt, previous_delay, self.clock.now());
+            let sleep_duration = match &result {
+                Ok(response) => policy.backoff_time(response, &state)?,
+                Err(error) => policy.error_backoff_time(error, &state),
+            };
+            if let Some(max_elapsed) = max_elapsed {
+                let elapsed = elapsed();
+                if elapsed + sleep_duration > max_elapsed {
+                    warn!(
+                        "Next attempt would exceed the maximum elapsed time of {:?}",
+                        max_elapsed
+                    );
+                    history.push(record);
+                    let (last_response, last_error) = summarize_result(result).await;
+                    return Err(ReqwestBackoffError::DeadlineExceeded {
+                        elapsed,
+                        attempts: *attempt,
+                        history,
+                        last_response,
+                        last_error,
+                    });
+                }
+            }
+            record.delay = Some(sleep_duration);
+            let event = RetryEvent {
+                url: request.url().clone(),
+                attempt: *attempt,
+                outcome: record.outcome.clone(),
+                delay: record.delay,
+                elapsed: record.elapsed,
+                policy: policy.clone(),
+            };
+            history.push(record);
+            if self.hooks.on_retry(event).await == RetryAction::Abort {
+                info!("Retrying was aborted by the on_retry hook");
+                return Err(ReqwestBackoffError::Aborted {
+                    attempts: *attempt,
+                    elapsed: elapsed(),
+                    history,
                 });
             }
-            let sleep_duration = get_backoff_time(&response, host, attempt)?;
-            info!("Sleeping for {} seconds", sleep_duration);
-            tokio::time::sleep(std::time::Duration::from_secs(sleep_duration)).await;
-            attempt += 1;
+            info!(wait = ?sleep_duration, "Sleeping for {} seconds", sleep_duration.as_secs());
+            self.sleeper.sleep(sleep_duration).await;
+            *attempt += 1;
+            previous_delay = Some(sleep_duration);
             info!("Backoff attempt #{}", attempt);
-            response = self
-                .client
-                .execute(request.try_clone().unwrap())
-                .await
-                .map_err(ReqwestBackoffError::Reqwest)?;
         }
-        Ok(response)
     }
-}
 
-#[tracing::instrument]
-fn get_host_from_request(request: &Request) -> HostType {
-    if let Some(Host::Domain(domain)) = request.url().host() {
-        match domain {
-            "twitch.tv" => HostType::Twitch,
-            "google.com" => HostType::Google,
-            "youtube.com" => HostType::Youtube,
-            _ => HostType::Other,
-        }
-    } else {
-        HostType::Other
+    pub(crate) fn elapsed_since(&self, start: DateTime<Utc>) -> Duration {
+        (self.clock.now() - start)
+            .to_std()
+            .unwrap_or(Duration::ZERO)
     }
-}
 
-#[tracing::instrument]
-fn is_backoff_limit_reached(attempt: u32, host: HostType) -> bool {
-    match host {
-        HostType::Twitch => attempt > MAX_BACKOFF_ATTEMPTS_TWITCH,
-        HostType::Google | HostType::Youtube => attempt > MAX_BACKOFF_ATTEMPTS_GOOGLE,
-        HostType::Other => attempt > MAX_BACKOFF_ATTEMPTS,
+    /// The timeout for the next attempt: the per-attempt timeout, limited by what is left of
+    /// the maximum elapsed time.
+    fn attempt_timeout(
+        &self,
+        elapsed: Duration,
+        max_elapsed: Option<Duration>,
+    ) -> Option<Duration> {
+        let remaining = max_elapsed.map(|max| max.saturating_sub(elapsed));
+        match (self.attempt_timeout, remaining) {
+            (Some(timeout), Some(remaining)) => Some(timeout.min(remaining)),
+            (timeout, remaining) => timeout.or(remaining),
+        }
     }
-}
 
-#[tracing::instrument]
-fn check_response_is_backoff(response: &Response, host: HostType) -> bool {
-    // dbg!(response, host);
-    let code = response.status();
-    if code.is_success() {
-        return false;
-    }
-    let code = code.as_u16();
-    match host {
-        HostType::Twitch => code == 429,
-        HostType::Google | HostType::Youtube => {
-            if !(code == 403 || code == 400) {
-                return false;
-            }
-            warn!("check_response_is_backoff->code: {}", code);
-            warn!("check_response_is_backoff->response: {:?}", response);
-            true
-        }
-        HostType::Other => false,
+    #[tracing::instrument]
+    fn get_policy_for_request(&self, request: &Request) -> &Arc<dyn BackoffPolicy> {
+        self.registry.resolve(request.url())
     }
 }
 
-#[tracing::instrument]
-fn get_backoff_time(response: &Response, host: HostType, attempt: u32) -> Result<u64> {
-    // dbg!(response, host);
-    Ok(match host {
-        HostType::Twitch => {
-            let timestamp = get_twitch_rate_limit_value(response)?;
-            let duration = chrono::Local::now().naive_utc().and_utc() - timestamp;
-            let duration = duration.num_seconds() as u64;
-            if duration > 0 {
-                duration
-            } else {
-                1
-            }
+fn timeout_error(
+    elapsed: Duration,
+    max_elapsed: Option<Duration>,
+    attempts: u32,
+    timeout: Duration,
+    mut history: Vec<AttemptRecord>,
+) -> ReqwestBackoffError {
+    history.push(AttemptRecord {
+        attempt: attempts,
+        outcome: AttemptOutcome::Error(format!("timed out after {:?}", timeout)),
+        elapsed,
+        delay: None,
+    });
+    if max_elapsed.is_some_and(|max| elapsed >= max) {
+        warn!("Reached the maximum elapsed time after {:?}", elapsed);
+        ReqwestBackoffError::DeadlineExceeded {
+            elapsed,
+            attempts,
+            history,
+            last_response: None,
+            last_error: None,
         }
-        HostType::Google | HostType::Youtube => {
-            let backoff_time = GOOGLE_BASE_BACKOFF_TIME_S.pow(attempt);
-            if backoff_time > GOOGLE_MAX_BACKOFF_TIME_S {
-                GOOGLE_MAX_BACKOFF_TIME_S
-            } else {
-                backoff_time
-            }
+    } else {
+        warn!("Attempt #{} timed out after {:?}", attempts, timeout);
+        ReqwestBackoffError::AttemptTimeout {
+            timeout,
+            elapsed,
+            attempts,
+            history,
         }
-        HostType::Other => 5,
-    })
+    }
 }
 
-#[tracing::instrument]
-fn get_twitch_rate_limit_value(response: &Response) -> Result<DateTime<Utc>> {
-    let timestamp = response
-        .headers()
-        .get("Ratelimit-Reset")
-        .unwrap()
-        .to_str()
+/// Reads the body of `response` for [`BackoffPolicy::check_body`] and puts it back afterwards, so
+/// the response can still be returned to the caller. Returns the decision along with the body.
+async fn check_response_body(
+    response: &mut Response,
+    policy: &dyn BackoffPolicy,
+) -> Result<(bool, Bytes)> {
+    let mut builder = http::Response::builder()
+        .status(response.status())
+        .version(response.version())
+        .url(response.url().clone());
+    if let Some(headers) = builder.headers_mut() {
+        *headers = std::mem::take(response.headers_mut());
+    }
+    let taken = std::mem::replace(response, http::Response::new(Vec::new()).into());
+    let body = taken.bytes().await?;
+    let buffered: Response = builder
+        .body(body.clone())
         .map_err(|e| ReqwestBackoffError::Other(e.into()))?
-        .to_string()
-        .parse::<i64>()
-        .map_err(|e| ReqwestBackoffError::Other(e.into()))?;
-    let timestamp: DateTime<Utc> = DateTime::from_timestamp(timestamp, 0).ok_or(
-        ReqwestBackoffError::Other("Could not convert the provided timestamp".into()),
-    )?;
-    Ok(timestamp)
+        .into();
+    *response = buffered;
+    let retry = policy.check_body(response, &body)?;
+    Ok((retry, body))
+}
+
+pub(crate) async fn summarize_result(
+    result: std::result::Result<Response, reqwest::Error>,
+) -> (Option<Box<ResponseSummary>>, Option<reqwest::Error>) {
+    match result {
+        Ok(response) => (
+            Some(Box::new(ResponseSummary::from_response(response).await)),
+            None,
+        ),
+        Err(error) => (None, Some(error)),
+    }
 }
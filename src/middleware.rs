@@ -0,0 +1,81 @@
+//! A [`reqwest_middleware::Middleware`] running the backoff of a [`ReqwestClient`], for code that
+//! uses [`reqwest_middleware::ClientWithMiddleware`].
+//!
+//! ```no_run
+//! # use twba_reqwest_backoff::middleware::BackoffMiddleware;
+//! # use twba_reqwest_backoff::ReqwestClient;
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let backoff = ReqwestClient::builder().build()?;
+//! let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
+//!     .with(BackoffMiddleware::new(backoff))
+//!     .build();
+//! # Ok(())
+//! # }
+//! ```
+
+use http::Extensions;
+use reqwest::{Request, Response};
+use reqwest_middleware::{Middleware, Next};
+
+use crate::clock::BoxFuture;
+use crate::prelude::*;
+use crate::request::RequestOverrides;
+use crate::ReqwestClient;
+
+/// Retries requests that pass through it with the policies, rate limits, circuit breakers, hooks
+/// and token providers of a [`ReqwestClient`].
+///
+/// Every attempt runs the rest of the middleware chain with a clone of the request's extensions,
+/// so changes later middleware makes to them are not visible to earlier ones. The wrapped
+/// [`reqwest::Client`] is not used.
+///
+/// [`RequestOverrides`] in the extensions, for example added with
+/// [`RequestBuilder::with_extension`](reqwest_middleware::RequestBuilder::with_extension), are
+/// merged with the policy for the host.
+#[derive(Debug, Clone)]
+pub struct BackoffMiddleware {
+    client: ReqwestClient,
+}
+
+impl BackoffMiddleware {
+    pub fn new(client: ReqwestClient) -> Self {
+        Self { client }
+    }
+}
+
+#[async_trait::async_trait]
+impl Middleware for BackoffMiddleware {
+    async fn handle(
+        &self,
+        request: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> reqwest_middleware::Result<Response> {
+        let extensions = &*extensions;
+        let send = |request: Request| -> BoxFuture<'_, _> {
+            let next = next.clone();
+            let mut extensions = extensions.clone();
+            Box::pin(async move {
+                match next.run(request, &mut extensions).await {
+                    Ok(response) => Ok(Ok(response)),
+                    Err(reqwest_middleware::Error::Reqwest(error)) => Ok(Err(error)),
+                    Err(reqwest_middleware::Error::Middleware(error)) => {
+                        Err(ReqwestBackoffError::Other(error.into()))
+                    }
+                }
+            })
+        };
+        let overrides = extensions
+            .get::<RequestOverrides>()
+            .cloned()
+            .unwrap_or_default();
+        let result = self
+            .client
+            .execute_with_overrides(request, &overrides, &send)
+            .await;
+        result.map_err(|error| match error {
+            ReqwestBackoffError::Reqwest(error) => reqwest_middleware::Error::Reqwest(error),
+            error => reqwest_middleware::Error::middleware(error),
+        })
+    }
+}
@@ -0,0 +1,344 @@
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use reqwest::{Request, Response, StatusCode};
+
+use crate::config::BackoffConfig;
+use crate::google::{is_ambiguous_status, GoogleError, RETRYABLE_REASONS};
+use crate::headers::{is_retry_after_status, retry_after_time, TwitchRateLimit};
+use crate::jitter::JitterRng;
+use crate::prelude::*;
+
+/// The state of the retry loop, passed to a [`BackoffPolicy`] when computing a delay.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RetryState {
+    /// The number of the attempt that just failed, starting at 1.
+    pub attempt: u32,
+    /// The delay before the attempt that just failed, if it was a retry.
+    pub previous_delay: Option<Duration>,
+    /// The current time, according to the client's [`Clock`](crate::clock::Clock).
+    pub now: DateTime<Utc>,
+}
+
+impl RetryState {
+    pub fn new(attempt: u32, previous_delay: Option<Duration>, now: DateTime<Utc>) -> Self {
+        Self {
+            attempt,
+            previous_delay,
+            now,
+        }
+    }
+}
+
+/// Decides if and how a request should be retried for a specific host.
+///
+/// The [`ReqwestClient`](crate::ReqwestClient) looks up the policy for the host of each request
+/// and consults it after every attempt.
+pub trait BackoffPolicy: Debug + Send + Sync {
+    /// Returns `true` if the response indicates that the request should be retried.
+    fn should_backoff(&self, response: &Response) -> bool;
+
+    /// Returns how long to wait before the next attempt.
+    fn backoff_time(&self, response: &Response, state: &RetryState) -> Result<Duration>;
+
+    /// The maximum number of attempts, including the first one, before giving up.
+    fn max_attempts(&self) -> u32;
+
+    /// Returns `true` if the request should be retried after it failed with `error`.
+    ///
+    /// Defaults to never retrying transport errors.
+    fn should_retry_error(&self, error: &reqwest::Error) -> bool {
+        let _ = error;
+        false
+    }
+
+    /// Returns how long to wait before the next attempt after a transport error.
+    ///
+    /// Defaults to the delays of [`BackoffConfig::default`].
+    fn error_backoff_time(&self, error: &reqwest::Error, state: &RetryState) -> Duration {
+        let _ = error;
+        BackoffConfig::default().delay_for_attempt(state.attempt)
+    }
+
+    /// Returns how long to wait before sending `request`, to avoid running into a rate limit.
+    ///
+    /// Called before every attempt with the current time. Defaults to not waiting.
+    fn delay_before_request(&self, request: &Request, now: DateTime<Utc>) -> Option<Duration> {
+        let _ = (request, now);
+        None
+    }
+
+    /// Returns `true` if the body of `response` is needed to decide whether to retry.
+    ///
+    /// Only called for responses [`should_backoff`](Self::should_backoff) returned `true` for.
+    /// If this returns `true`, the body is read and passed to [`check_body`](Self::check_body).
+    fn needs_body(&self, response: &Response) -> bool {
+        let _ = response;
+        false
+    }
+
+    /// Decides whether to retry based on the body of the response.
+    ///
+    /// Returns `Ok(true)` to retry, `Ok(false)` to return the response to the caller, or an error
+    /// to fail immediately. Defaults to retrying.
+    fn check_body(&self, response: &Response, body: &[u8]) -> Result<bool> {
+        let _ = (response, body);
+        Ok(true)
+    }
+
+    /// Called with every response, so the policy can keep track of rate limit headers.
+    fn on_response(&self, response: &Response) {
+        let _ = response;
+    }
+}
+
+/// Policy for the Twitch API, which signals rate limits with `429` and the `Ratelimit-Reset` header.
+///
+/// `503` responses are retried as well, honoring `Retry-After` if present.
+///
+/// The `Ratelimit-*` headers of every response are tracked, and once the remaining points drop to
+/// the reserve, requests are delayed until the bucket resets instead of running into a `429`.
+/// Clones share this state.
+#[derive(Debug, Clone)]
+pub struct TwitchPolicy {
+    config: BackoffConfig,
+    rng: JitterRng,
+    reserve: u32,
+    rate_limit: Arc<Mutex<Option<TwitchRateLimit>>>,
+}
+
+impl TwitchPolicy {
+    pub fn new(config: BackoffConfig) -> Self {
+        Self {
+            config,
+            rng: JitterRng::new(config.jitter_seed),
+            reserve: 0,
+            rate_limit: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// The number of points that are left unused before requests are delayed until the reset.
+    pub fn with_reserve(mut self, reserve: u32) -> Self {
+        self.reserve = reserve;
+        self
+    }
+}
+
+impl Default for TwitchPolicy {
+    fn default() -> Self {
+        Self::new(BackoffConfig::twitch())
+    }
+}
+
+impl BackoffPolicy for TwitchPolicy {
+    fn should_backoff(&self, response: &Response) -> bool {
+        is_retry_after_status(response.status())
+    }
+
+    #[tracing::instrument]
+    fn backoff_time(&self, response: &Response, state: &RetryState) -> Result<Duration> {
+        if response.status() != StatusCode::TOO_MANY_REQUESTS {
+            return Ok(retry_after_time(response, &self.config, state.now)
+                .unwrap_or_else(|| self.config.jittered_delay(state, &self.rng)));
+        }
+        match TwitchRateLimit::from_headers(response.headers()) {
+            Ok(Some(TwitchRateLimit {
+                reset: Some(reset), ..
+            })) => {
+                let wait = (reset - state.now).to_std().unwrap_or(Duration::ZERO);
+                // The reset is only accurate to the second, so wait at least one second
+                Ok(wait
+                    .max(Duration::from_secs(1))
+                    .min(self.config.max_retry_after))
+            }
+            Err(e) => {
+                warn!("Twitch responded with 429 and {}", e);
+                Ok(retry_after_time(response, &self.config, state.now)
+                    .unwrap_or_else(|| self.config.jittered_delay(state, &self.rng)))
+            }
+            _ => {
+                warn!("Twitch responded with 429 without a Ratelimit-Reset header");
+                Ok(retry_after_time(response, &self.config, state.now)
+                    .unwrap_or_else(|| self.config.jittered_delay(state, &self.rng)))
+            }
+        }
+    }
+
+    fn max_attempts(&self) -> u32 {
+        self.config.max_attempts
+    }
+
+    fn should_retry_error(&self, error: &reqwest::Error) -> bool {
+        self.config.retry_on.matches(error)
+    }
+
+    fn error_backoff_time(&self, _error: &reqwest::Error, state: &RetryState) -> Duration {
+        self.config.jittered_delay(state, &self.rng)
+    }
+
+    fn delay_before_request(&self, _request: &Request, now: DateTime<Utc>) -> Option<Duration> {
+        let mut rate_limit = self.rate_limit.lock().unwrap();
+        let wait = rate_limit.as_mut()?.acquire(self.reserve, now);
+        if let Some(wait) = wait {
+            info!(
+                ?wait,
+                "Twitch rate limit is almost used up, waiting for the reset"
+            );
+        }
+        wait
+    }
+
+    fn on_response(&self, response: &Response) {
+        if let Ok(Some(update)) = TwitchRateLimit::from_headers(response.headers()) {
+            let mut rate_limit = self.rate_limit.lock().unwrap();
+            *rate_limit = Some(update);
+        }
+    }
+}
+
+/// Policy for Google APIs (including YouTube), which signal rate limits with `403` or `400`.
+///
+/// Since these statuses are also used for permanent failures, the `reason` in the error body
+/// decides whether to retry. Only the [retryable reasons](RETRYABLE_REASONS) are retried, other
+/// reasons fail with [`ReqwestBackoffError::GoogleApi`].
+///
+/// `429` and `503` are retried as well. Uses `Retry-After` if present and an exponential backoff,
+/// which by default is capped at one hour, otherwise.
+#[derive(Debug, Clone)]
+pub struct GooglePolicy {
+    config: BackoffConfig,
+    rng: JitterRng,
+    retryable_reasons: Vec<String>,
+}
+
+impl GooglePolicy {
+    pub fn new(config: BackoffConfig) -> Self {
+        Self {
+            config,
+            rng: JitterRng::new(config.jitter_seed),
+            retryable_reasons: RETRYABLE_REASONS.iter().map(|r| r.to_string()).collect(),
+        }
+    }
+
+    /// Replace the reasons that are retried.
+    pub fn with_retryable_reasons<I, S>(mut self, reasons: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.retryable_reasons = reasons.into_iter().map(Into::into).collect();
+        self
+    }
+}
+
+impl Default for GooglePolicy {
+    fn default() -> Self {
+        Self::new(BackoffConfig::google())
+    }
+}
+
+impl BackoffPolicy for GooglePolicy {
+    #[tracing::instrument]
+    fn should_backoff(&self, response: &Response) -> bool {
+        if is_retry_after_status(response.status()) {
+            return true;
+        }
+        if !is_ambiguous_status(response.status()) {
+            return false;
+        }
+        debug!(
+            "Got {}, checking the body for a retryable reason",
+            response.status()
+        );
+        true
+    }
+
+    fn needs_body(&self, response: &Response) -> bool {
+        is_ambiguous_status(response.status())
+    }
+
+    #[tracing::instrument(skip(self, body))]
+    fn check_body(&self, response: &Response, body: &[u8]) -> Result<bool> {
+        let Some(error) = GoogleError::from_body(body) else {
+            warn!("Response is not a Google API error, not retrying");
+            return Ok(false);
+        };
+        let reason = error.reason().unwrap_or_default();
+        if self.retryable_reasons.iter().any(|r| r == reason) {
+            info!("Google API error reason {} is retryable", reason);
+            return Ok(true);
+        }
+        Err(ReqwestBackoffError::GoogleApi {
+            status: response.status(),
+            reason: reason.to_string(),
+            error,
+        })
+    }
+
+    fn backoff_time(&self, response: &Response, state: &RetryState) -> Result<Duration> {
+        Ok(retry_after_time(response, &self.config, state.now)
+            .unwrap_or_else(|| self.config.jittered_delay(state, &self.rng)))
+    }
+
+    fn max_attempts(&self) -> u32 {
+        self.config.max_attempts
+    }
+
+    fn should_retry_error(&self, error: &reqwest::Error) -> bool {
+        self.config.retry_on.matches(error)
+    }
+
+    fn error_backoff_time(&self, _error: &reqwest::Error, state: &RetryState) -> Duration {
+        self.config.jittered_delay(state, &self.rng)
+    }
+}
+
+/// Policy used for hosts without a dedicated policy.
+///
+/// Retries `429` and `503` responses, waiting as long as `Retry-After` requests if present.
+#[derive(Debug, Clone)]
+pub struct DefaultPolicy {
+    config: BackoffConfig,
+    rng: JitterRng,
+}
+
+impl DefaultPolicy {
+    pub fn new(config: BackoffConfig) -> Self {
+        Self {
+            config,
+            rng: JitterRng::new(config.jitter_seed),
+        }
+    }
+}
+
+impl Default for DefaultPolicy {
+    fn default() -> Self {
+        Self::new(BackoffConfig::default())
+    }
+}
+
+impl BackoffPolicy for DefaultPolicy {
+    fn should_backoff(&self, response: &Response) -> bool {
+        is_retry_after_status(response.status())
+    }
+
+    fn backoff_time(&self, response: &Response, state: &RetryState) -> Result<Duration> {
+        Ok(retry_after_time(response, &self.config, state.now)
+            .unwrap_or_else(|| self.config.jittered_delay(state, &self.rng)))
+    }
+
+    fn max_attempts(&self) -> u32 {
+        self.config.max_attempts
+    }
+
+    fn should_retry_error(&self, error: &reqwest::Error) -> bool {
+        self.config.retry_on.matches(error)
+    }
+
+    fn error_backoff_time(&self, _error: &reqwest::Error, state: &RetryState) -> Duration {
+        self.config.jittered_delay(state, &self.rng)
+    }
+}
@@ -2,5 +2,5 @@ pub(crate) use std::error::Error as StdError;
 
 pub use crate::ReqwestBackoffError;
 
-pub(crate) use tracing::{info, warn};
+pub(crate) use tracing::{debug, info, warn};
 pub(crate) type Result<T> = std::result::Result<T, ReqwestBackoffError>;
@@ -0,0 +1,154 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use reqwest::header::HeaderMap;
+use url::Url;
+
+use crate::headers::TwitchRateLimit;
+use crate::prelude::*;
+use crate::registry::{best_match, HostPattern};
+
+/// Limits how many requests are sent to a host from the client side.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    /// How many requests may be sent per second on average.
+    pub requests_per_second: f64,
+    /// How many requests may be sent at once after being idle.
+    pub burst: u32,
+}
+
+impl RateLimit {
+    pub fn new(requests_per_second: f64, burst: u32) -> Self {
+        Self {
+            requests_per_second,
+            burst,
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if !self.requests_per_second.is_finite() || self.requests_per_second <= 0.0 {
+            return Err(ReqwestBackoffError::InvalidConfig(
+                "requests_per_second must be a finite number greater than zero".to_string(),
+            ));
+        }
+        if self.burst == 0 {
+            return Err(ReqwestBackoffError::InvalidConfig(
+                "burst must be at least 1".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    limit: RateLimit,
+    /// Can be negative if requests are already waiting for tokens.
+    tokens: f64,
+    last_refill: DateTime<Utc>,
+    /// Set if the server told us that no requests are allowed until then.
+    blocked_until: Option<DateTime<Utc>>,
+}
+
+impl TokenBucket {
+    fn new(limit: RateLimit, now: DateTime<Utc>) -> Self {
+        Self {
+            limit,
+            tokens: limit.burst as f64,
+            last_refill: now,
+            blocked_until: None,
+        }
+    }
+
+    fn refill(&mut self, now: DateTime<Utc>) {
+        if let Some(blocked_until) = self.blocked_until {
+            if now < blocked_until {
+                return;
+            }
+            // The server refilled the bucket, minus the tokens already promised to waiting requests
+            self.blocked_until = None;
+            self.tokens = (self.tokens + self.limit.burst as f64).min(self.limit.burst as f64);
+            self.last_refill = blocked_until;
+        }
+        let elapsed = (now - self.last_refill).to_std().unwrap_or(Duration::ZERO);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.limit.requests_per_second)
+            .min(self.limit.burst as f64);
+        self.last_refill = now;
+    }
+
+    /// Takes a token and returns how long the caller has to wait until it may use it.
+    fn acquire(&mut self, now: DateTime<Utc>) -> Option<Duration> {
+        self.refill(now);
+        let blocked = self
+            .blocked_until
+            .and_then(|until| (until - now).to_std().ok())
+            .unwrap_or(Duration::ZERO);
+        self.tokens -= 1.0;
+        // While blocked, waiting requests get their tokens from the refill at the reset
+        let available = if blocked.is_zero() {
+            self.tokens
+        } else {
+            self.tokens + self.limit.burst as f64
+        };
+        let missing = if available < 0.0 {
+            Duration::from_secs_f64(-available / self.limit.requests_per_second)
+        } else {
+            Duration::ZERO
+        };
+        let wait = blocked + missing;
+        (!wait.is_zero()).then_some(wait)
+    }
+
+    /// Syncs the bucket with the rate limit reported by the server.
+    fn observe(&mut self, rate_limit: TwitchRateLimit, now: DateTime<Utc>) {
+        self.refill(now);
+        if let Some(remaining) = rate_limit.remaining {
+            self.tokens = self.tokens.min(remaining as f64);
+            if remaining == 0 {
+                self.blocked_until = rate_limit.reset.filter(|reset| *reset > now);
+            }
+        }
+    }
+}
+
+/// Per-host token buckets, shared between all clones of a [`ReqwestClient`](crate::ReqwestClient).
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RateLimiter {
+    buckets: Arc<Vec<(HostPattern, Mutex<TokenBucket>)>>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limits: Vec<(HostPattern, RateLimit)>, now: DateTime<Utc>) -> Self {
+        let buckets = limits
+            .into_iter()
+            .map(|(pattern, limit)| (pattern, Mutex::new(TokenBucket::new(limit, now))))
+            .collect();
+        Self {
+            buckets: Arc::new(buckets),
+        }
+    }
+
+    fn bucket(&self, url: &Url) -> Option<&Mutex<TokenBucket>> {
+        best_match(&self.buckets, url).map(|(_, bucket)| bucket)
+    }
+
+    /// Takes a token for a request to `url` and returns how long to wait before sending it.
+    pub(crate) fn acquire(&self, url: &Url, now: DateTime<Utc>) -> Option<Duration> {
+        let wait = self.bucket(url)?.lock().unwrap().acquire(now);
+        if let Some(wait) = wait {
+            info!(?wait, "Client side rate limit reached for {}", url);
+        }
+        wait
+    }
+
+    /// Refills the bucket for `url` from rate limit headers, if the response has any.
+    pub(crate) fn observe(&self, url: &Url, headers: &HeaderMap, now: DateTime<Utc>) {
+        let Some(bucket) = self.bucket(url) else {
+            return;
+        };
+        if let Ok(Some(rate_limit)) = TwitchRateLimit::from_headers(headers) {
+            bucket.lock().unwrap().observe(rate_limit, now);
+        }
+    }
+}
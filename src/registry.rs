@@ -0,0 +1,410 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use url::Url;
+
+use crate::policy::{BackoffPolicy, DefaultPolicy, GooglePolicy, TwitchPolicy};
+use crate::prelude::*;
+
+/// Hosts that get a built-in policy in [`PolicyRegistry::default`].
+const TWITCH_HOSTS: &[&str] = &["twitch.tv", "*.twitch.tv"];
+const GOOGLE_HOSTS: &[&str] = &[
+    "google.com",
+    "*.google.com",
+    "googleapis.com",
+    "*.googleapis.com",
+    "youtube.com",
+    "*.youtube.com",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum HostMatch {
+    /// Matches every host (`*`).
+    Any,
+    /// Matches subdomains of the domain (`*.twitch.tv`), but not the domain itself.
+    Suffix(String),
+    /// Matches exactly this host.
+    Exact(String),
+}
+
+/// A pattern that is matched against the URL of a request.
+///
+/// Patterns have the form `[scheme://]host[:port][/path]`, where `host` is either an exact host
+/// (`api.twitch.tv`), a wildcard for all subdomains (`*.twitch.tv`) or `*` for any host.
+/// A path only matches whole segments, so `/helix` matches `/helix/users` but not `/helixfoo`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct HostPattern {
+    scheme: Option<String>,
+    host: HostMatch,
+    port: Option<u16>,
+    path_prefix: Option<String>,
+}
+
+impl HostPattern {
+    pub fn parse(pattern: &str) -> Result<Self> {
+        let invalid = || ReqwestBackoffError::InvalidHostPattern(pattern.to_string());
+        let pattern = pattern.trim();
+
+        let (scheme, rest) = match pattern.split_once("://") {
+            Some((scheme, rest)) if !scheme.is_empty() => (Some(scheme.to_ascii_lowercase()), rest),
+            Some(_) => return Err(invalid()),
+            None => (None, pattern),
+        };
+        // Schemes and hosts are case-insensitive, paths are not
+        let (authority, path_prefix) = match rest.find('/') {
+            Some(index) => {
+                let path = rest[index..].trim_end_matches('/');
+                let path = if path.is_empty() {
+                    None
+                } else {
+                    Some(path.to_string())
+                };
+                (rest[..index].to_ascii_lowercase(), path)
+            }
+            None => (rest.to_ascii_lowercase(), None),
+        };
+
+        let port_separator = match authority.rfind(']') {
+            Some(bracket) => authority[bracket..].find(':').map(|i| i + bracket),
+            None => authority.rfind(':'),
+        };
+        let (host, port) = match port_separator {
+            Some(index) => {
+                let port = authority[index + 1..]
+                    .parse::<u16>()
+                    .map_err(|_| invalid())?;
+                (&authority[..index], Some(port))
+            }
+            None => (authority.as_str(), None),
+        };
+
+        let host = match host {
+            "" => return Err(invalid()),
+            "*" => HostMatch::Any,
+            _ => match host.strip_prefix("*.") {
+                Some(domain) if !domain.is_empty() && !domain.contains('*') => {
+                    HostMatch::Suffix(domain.to_string())
+                }
+                Some(_) => return Err(invalid()),
+                None if host.contains('*') => return Err(invalid()),
+                None => HostMatch::Exact(host.to_string()),
+            },
+        };
+
+        Ok(Self {
+            scheme,
+            host,
+            port,
+            path_prefix,
+        })
+    }
+
+    /// Returns `true` if the pattern matches the given URL.
+    pub fn matches(&self, url: &Url) -> bool {
+        if let Some(scheme) = &self.scheme {
+            if scheme != url.scheme() {
+                return false;
+            }
+        }
+        if let Some(port) = self.port {
+            if Some(port) != url.port_or_known_default() {
+                return false;
+            }
+        }
+        let host_matches = match (&self.host, url.host_str()) {
+            (HostMatch::Any, _) => true,
+            (_, None) => false,
+            (HostMatch::Exact(expected), Some(host)) => expected == host,
+            (HostMatch::Suffix(domain), Some(host)) => host
+                .strip_suffix(domain.as_str())
+                .is_some_and(|sub| sub.len() > 1 && sub.ends_with('.')),
+        };
+        if !host_matches {
+            return false;
+        }
+        match &self.path_prefix {
+            None => true,
+            Some(prefix) => {
+                let path = url.path();
+                path.strip_prefix(prefix.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+            }
+        }
+    }
+
+    /// How specific this pattern is. When multiple patterns match, the most specific one wins.
+    ///
+    /// Exact hosts beat wildcards, longer domains beat shorter ones, then longer paths, a port
+    /// and a scheme each make a pattern more specific.
+    fn specificity(&self) -> (u8, usize, usize, bool, bool) {
+        let (host_rank, host_len) = match &self.host {
+            HostMatch::Any => (0, 0),
+            HostMatch::Suffix(domain) => (1, domain.len()),
+            HostMatch::Exact(host) => (2, host.len()),
+        };
+        let path_len = self.path_prefix.as_ref().map_or(0, String::len);
+        (
+            host_rank,
+            host_len,
+            path_len,
+            self.port.is_some(),
+            self.scheme.is_some(),
+        )
+    }
+}
+
+impl FromStr for HostPattern {
+    type Err = ReqwestBackoffError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::parse(s)
+    }
+}
+
+impl Display for HostPattern {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(scheme) = &self.scheme {
+            write!(f, "{}://", scheme)?;
+        }
+        match &self.host {
+            HostMatch::Any => write!(f, "*")?,
+            HostMatch::Suffix(domain) => write!(f, "*.{}", domain)?,
+            HostMatch::Exact(host) => write!(f, "{}", host)?,
+        }
+        if let Some(port) = self.port {
+            write!(f, ":{}", port)?;
+        }
+        if let Some(path) = &self.path_prefix {
+            write!(f, "{}", path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the entry with the most specific pattern matching `url`.
+pub(crate) fn best_match<'a, T>(
+    entries: &'a [(HostPattern, T)],
+    url: &Url,
+) -> Option<&'a (HostPattern, T)> {
+    entries
+        .iter()
+        .filter(|(pattern, _)| pattern.matches(url))
+        .max_by_key(|(pattern, _)| pattern.specificity())
+}
+
+/// Maps [`HostPattern`]s to the [`BackoffPolicy`] that should be used for matching requests.
+///
+/// If multiple patterns match a URL, the most specific one is used. URLs that match no pattern
+/// use the fallback policy.
+#[derive(Debug, Clone)]
+pub struct PolicyRegistry {
+    entries: Vec<(HostPattern, Arc<dyn BackoffPolicy>)>,
+    fallback: Arc<dyn BackoffPolicy>,
+}
+
+impl Default for PolicyRegistry {
+    /// A registry with the built-in policies for the Twitch and Google APIs.
+    fn default() -> Self {
+        Self::builtin(
+            TwitchPolicy::default(),
+            GooglePolicy::default(),
+            DefaultPolicy::default(),
+        )
+    }
+}
+
+impl PolicyRegistry {
+    /// A registry with the built-in host entries, using the given policies for them.
+    pub(crate) fn builtin(
+        twitch: TwitchPolicy,
+        google: GooglePolicy,
+        fallback: DefaultPolicy,
+    ) -> Self {
+        let mut registry = Self::empty(fallback);
+        let twitch: Arc<dyn BackoffPolicy> = Arc::new(twitch);
+        let google: Arc<dyn BackoffPolicy> = Arc::new(google);
+        for host in TWITCH_HOSTS {
+            registry.insert_arc(HostPattern::parse(host).unwrap(), twitch.clone());
+        }
+        for host in GOOGLE_HOSTS {
+            registry.insert_arc(HostPattern::parse(host).unwrap(), google.clone());
+        }
+        registry
+    }
+
+    /// A registry without any entries that uses `fallback` for every request.
+    pub fn empty(fallback: impl BackoffPolicy + 'static) -> Self {
+        Self {
+            entries: Vec::new(),
+            fallback: Arc::new(fallback),
+        }
+    }
+
+    /// Use `policy` for requests matching `pattern`, replacing any policy set for the same pattern.
+    pub fn insert(&mut self, pattern: HostPattern, policy: impl BackoffPolicy + 'static) {
+        self.insert_arc(pattern, Arc::new(policy));
+    }
+
+    /// Like [`insert`](Self::insert), but allows sharing one policy between multiple patterns.
+    pub fn insert_arc(&mut self, pattern: HostPattern, policy: Arc<dyn BackoffPolicy>) {
+        match self.entries.iter_mut().find(|(p, _)| *p == pattern) {
+            Some(entry) => entry.1 = policy,
+            None => self.entries.push((pattern, policy)),
+        }
+    }
+
+    /// Removes the policy for `pattern`, returning it if there was one.
+    pub fn remove(&mut self, pattern: &HostPattern) -> Option<Arc<dyn BackoffPolicy>> {
+        let index = self.entries.iter().position(|(p, _)| p == pattern)?;
+        Some(self.entries.remove(index).1)
+    }
+
+    /// Use `policy` for requests that match none of the patterns.
+    pub fn set_fallback(&mut self, policy: impl BackoffPolicy + 'static) {
+        self.fallback = Arc::new(policy);
+    }
+
+    /// Returns the policy for `url` along with the pattern that selected it, if any.
+    pub fn resolve_with_pattern(
+        &self,
+        url: &Url,
+    ) -> (Option<&HostPattern>, &Arc<dyn BackoffPolicy>) {
+        best_match(&self.entries, url).map_or((None, &self.fallback), |(pattern, policy)| {
+            (Some(pattern), policy)
+        })
+    }
+
+    /// Returns the policy that should be used for `url`.
+    pub fn resolve(&self, url: &Url) -> &Arc<dyn BackoffPolicy> {
+        self.resolve_with_pattern(url).1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    fn matches(pattern: &str, url_str: &str) -> bool {
+        HostPattern::parse(pattern).unwrap().matches(&url(url_str))
+    }
+
+    #[test]
+    fn exact_hosts_ignore_case_but_paths_do_not() {
+        assert!(matches("API.Twitch.tv", "https://api.twitch.tv/helix"));
+        assert!(matches("HTTPS://example.com", "https://example.com/"));
+        assert!(matches(
+            "example.com/Upload",
+            "https://example.com/Upload/1"
+        ));
+        assert!(!matches(
+            "example.com/Upload",
+            "https://example.com/upload/1"
+        ));
+        assert!(!matches("api.twitch.tv", "https://twitch.tv/"));
+    }
+
+    #[test]
+    fn wildcards_match_subdomains_only() {
+        assert!(matches("*.twitch.tv", "https://api.twitch.tv/"));
+        assert!(matches("*.twitch.tv", "https://a.b.twitch.tv/"));
+        assert!(!matches("*.twitch.tv", "https://twitch.tv/"));
+        assert!(!matches("*.twitch.tv", "https://nottwitch.tv/"));
+        assert!(matches("*", "https://anything.example/"));
+    }
+
+    #[test]
+    fn ports_and_schemes_must_match() {
+        assert!(matches("127.0.0.1:8080", "http://127.0.0.1:8080/"));
+        assert!(!matches("127.0.0.1:8080", "http://127.0.0.1:9090/"));
+        assert!(matches("example.com:443", "https://example.com/"));
+        assert!(matches("http://example.com", "http://example.com/"));
+        assert!(!matches("http://example.com", "https://example.com/"));
+    }
+
+    #[test]
+    fn ipv6_hosts_keep_their_brackets() {
+        assert!(matches("[::1]", "http://[::1]:8080/"));
+        assert!(matches("[::1]:8080", "http://[::1]:8080/"));
+        assert!(!matches("[::1]:8080", "http://[::1]:9090/"));
+        assert_eq!(
+            HostPattern::parse("[::1]:8080").unwrap().to_string(),
+            "[::1]:8080"
+        );
+    }
+
+    #[test]
+    fn paths_match_whole_segments() {
+        assert!(matches("example.com/helix", "https://example.com/helix"));
+        assert!(matches(
+            "example.com/helix/",
+            "https://example.com/helix/users"
+        ));
+        assert!(!matches(
+            "example.com/helix",
+            "https://example.com/helixfoo"
+        ));
+        assert!(!matches("example.com/helix", "https://example.com/"));
+    }
+
+    #[test]
+    fn invalid_patterns_are_rejected() {
+        for pattern in [
+            "",
+            "://example.com",
+            "example.com:port",
+            "*example.com",
+            "a.*.com",
+            "*.",
+        ] {
+            assert!(
+                matches!(
+                    HostPattern::parse(pattern),
+                    Err(ReqwestBackoffError::InvalidHostPattern(_))
+                ),
+                "{:?} should be invalid",
+                pattern
+            );
+        }
+    }
+
+    #[test]
+    fn the_most_specific_pattern_wins() {
+        let entries: Vec<_> = [
+            "*",
+            "*.example.com",
+            "*.api.example.com",
+            "v1.api.example.com",
+            "v1.api.example.com/upload",
+            "v1.api.example.com:443/upload",
+            "https://v1.api.example.com:443/upload",
+        ]
+        .into_iter()
+        .map(|pattern| (HostPattern::parse(pattern).unwrap(), pattern))
+        .collect();
+        let best = |url_str: &str| best_match(&entries, &url(url_str)).map(|(_, name)| *name);
+
+        assert_eq!(best("https://other.test/"), Some("*"));
+        assert_eq!(best("https://www.example.com/"), Some("*.example.com"));
+        assert_eq!(
+            best("https://v2.api.example.com/"),
+            Some("*.api.example.com")
+        );
+        assert_eq!(
+            best("http://v1.api.example.com/"),
+            Some("v1.api.example.com")
+        );
+        assert_eq!(
+            best("http://v1.api.example.com/upload/1"),
+            Some("v1.api.example.com/upload")
+        );
+        assert_eq!(
+            best("https://v1.api.example.com/upload/1"),
+            Some("https://v1.api.example.com:443/upload")
+        );
+    }
+}
@@ -0,0 +1,326 @@
+//! Sending requests with backoff from a [`reqwest::RequestBuilder`], with per-request overrides.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use reqwest::{Request, RequestBuilder, Response, StatusCode};
+
+use crate::clock::BoxFuture;
+use crate::policy::{BackoffPolicy, RetryState};
+use crate::prelude::*;
+use crate::ReqwestClient;
+
+/// Changes to the host policy for a single request.
+///
+/// [`ReqwestClient::execute_with_backoff`] reads them from the extensions of the request, where
+/// [`attach_to`](Self::attach_to) puts them. The parts that are not overridden come from the
+/// policy for the host, or from [`policy`](Self::policy) if that is set.
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// # use twba_reqwest_backoff::{ReqwestClient, RequestOverrides};
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let client = ReqwestClient::new();
+/// let request = client.get("https://www.googleapis.com/youtube/v3/videos").build()?;
+/// let request = RequestOverrides::new()
+///     .with_max_attempts(200)
+///     .with_deadline(Duration::from_secs(6 * 60 * 60))
+///     .attach_to(request)?;
+/// let response = client.execute_with_backoff(request).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct RequestOverrides {
+    /// Replaces [`BackoffPolicy::max_attempts`].
+    pub max_attempts: Option<u32>,
+    /// Replaces the maximum elapsed time of the client.
+    pub deadline: Option<Duration>,
+    /// Replaces the policy for the host.
+    pub policy: Option<Arc<dyn BackoffPolicy>>,
+    /// Only responses with these statuses are retried, instead of those the policy retries.
+    pub retryable_statuses: Option<Vec<StatusCode>>,
+    /// Send the request once and return the response or error as is.
+    pub no_retry: bool,
+}
+
+impl RequestOverrides {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = Some(max_attempts);
+        self
+    }
+
+    pub fn with_deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    pub fn with_policy(mut self, policy: impl BackoffPolicy + 'static) -> Self {
+        self.policy = Some(Arc::new(policy));
+        self
+    }
+
+    pub fn with_retryable_statuses(
+        mut self,
+        statuses: impl IntoIterator<Item = StatusCode>,
+    ) -> Self {
+        self.retryable_statuses = Some(statuses.into_iter().collect());
+        self
+    }
+
+    pub fn no_retry(mut self) -> Self {
+        self.no_retry = true;
+        self
+    }
+
+    /// Stores the overrides in the extensions of `request`.
+    ///
+    /// With an [`http::Request`], insert them into its extensions before converting it instead.
+    pub fn attach_to(self, request: Request) -> Result<Request> {
+        let had_body = request.body().is_some();
+        let mut http_request = http::Request::<reqwest::Body>::try_from(request)?;
+        http_request.extensions_mut().insert(self);
+        into_reqwest_request(http_request, had_body)
+    }
+
+    /// The policy for the request, based on the policy for its host.
+    pub(crate) fn apply(&self, host_policy: &Arc<dyn BackoffPolicy>) -> Arc<dyn BackoffPolicy> {
+        let policy = self.policy.as_ref().unwrap_or(host_policy);
+        if self.max_attempts.is_none() && self.retryable_statuses.is_none() && !self.no_retry {
+            return policy.clone();
+        }
+        Arc::new(OverriddenPolicy {
+            inner: policy.clone(),
+            max_attempts: self.max_attempts,
+            retryable_statuses: self.retryable_statuses.clone(),
+            no_retry: self.no_retry,
+        })
+    }
+}
+
+/// Removes the [`RequestOverrides`] from the extensions of `request`.
+///
+/// reqwest doesn't expose the extensions of a [`Request`], so this goes through [`http::Request`].
+pub(crate) fn take_overrides(request: Request) -> Result<(Request, RequestOverrides)> {
+    // Requests that can't be converted can't have been given overrides either
+    if http::Uri::try_from(request.url().as_str()).is_err() {
+        return Ok((request, RequestOverrides::default()));
+    }
+    let had_body = request.body().is_some();
+    let mut http_request = http::Request::<reqwest::Body>::try_from(request)?;
+    let overrides = http_request.extensions_mut().remove::<RequestOverrides>();
+    let request = into_reqwest_request(http_request, had_body)?;
+    Ok((request, overrides.unwrap_or_default()))
+}
+
+/// Converts back, without the empty body the conversion to [`http::Request`] added.
+fn into_reqwest_request(request: http::Request<reqwest::Body>, had_body: bool) -> Result<Request> {
+    let mut request = Request::try_from(request)?;
+    if !had_body {
+        *request.body_mut() = None;
+    }
+    Ok(request)
+}
+
+/// Delegates to the policy for the host, except for the overridden parts.
+#[derive(Debug)]
+struct OverriddenPolicy {
+    inner: Arc<dyn BackoffPolicy>,
+    max_attempts: Option<u32>,
+    retryable_statuses: Option<Vec<StatusCode>>,
+    no_retry: bool,
+}
+
+impl BackoffPolicy for OverriddenPolicy {
+    fn should_backoff(&self, response: &Response) -> bool {
+        if self.no_retry {
+            return false;
+        }
+        match &self.retryable_statuses {
+            Some(statuses) => statuses.contains(&response.status()),
+            None => self.inner.should_backoff(response),
+        }
+    }
+
+    fn backoff_time(&self, response: &Response, state: &RetryState) -> Result<Duration> {
+        self.inner.backoff_time(response, state)
+    }
+
+    fn max_attempts(&self) -> u32 {
+        self.max_attempts
+            .unwrap_or_else(|| self.inner.max_attempts())
+    }
+
+    fn should_retry_error(&self, error: &reqwest::Error) -> bool {
+        !self.no_retry && self.inner.should_retry_error(error)
+    }
+
+    fn error_backoff_time(&self, error: &reqwest::Error, state: &RetryState) -> Duration {
+        self.inner.error_backoff_time(error, state)
+    }
+
+    fn delay_before_request(&self, request: &Request, now: DateTime<Utc>) -> Option<Duration> {
+        self.inner.delay_before_request(request, now)
+    }
+
+    fn needs_body(&self, response: &Response) -> bool {
+        self.inner.needs_body(response)
+    }
+
+    fn check_body(&self, response: &Response, body: &[u8]) -> Result<bool> {
+        self.inner.check_body(response, body)
+    }
+
+    fn on_response(&self, response: &Response) {
+        self.inner.on_response(response)
+    }
+}
+
+/// Sends requests built with a [`RequestBuilder`] through a [`ReqwestClient`]:
+///
+/// ```no_run
+/// # use twba_reqwest_backoff::{ReqwestClient, RequestBuilderExt};
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let client = ReqwestClient::new();
+/// let response = client
+///     .get("https://api.twitch.tv/helix/videos")
+///     .query(&[("user_id", "1234")])
+///     .max_attempts(5)
+///     .send_with_backoff(&client)
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// The overrides return a [`BackoffRequestBuilder`], so they come after the other parts of the
+/// request.
+pub trait RequestBuilderExt {
+    /// Builds the request and sends it with [`ReqwestClient::execute_with_backoff`].
+    ///
+    /// Errors while building the request fail with [`ReqwestBackoffError::Build`].
+    fn send_with_backoff(self, client: &ReqwestClient) -> BoxFuture<'_, Result<Response>>;
+
+    /// Overrides the maximum number of attempts of the host policy, including the first one.
+    ///
+    /// Unlike [`no_retry`](Self::no_retry), a retryable response on the last attempt still fails
+    /// with [`ReqwestBackoffError::BackoffExceeded`].
+    fn max_attempts(self, max_attempts: u32) -> BackoffRequestBuilder;
+
+    /// Sends the request once and returns the response or error as is.
+    fn no_retry(self) -> BackoffRequestBuilder;
+
+    /// Uses `policy` instead of the policy for the host.
+    fn policy(self, policy: impl BackoffPolicy + 'static) -> BackoffRequestBuilder;
+
+    /// Overrides the maximum elapsed time of the client.
+    fn deadline(self, deadline: Duration) -> BackoffRequestBuilder;
+
+    /// Only retries responses with these statuses, instead of those the policy retries.
+    fn retryable_statuses(
+        self,
+        statuses: impl IntoIterator<Item = StatusCode>,
+    ) -> BackoffRequestBuilder;
+
+    /// Replaces all overrides set so far.
+    fn overrides(self, overrides: RequestOverrides) -> BackoffRequestBuilder;
+}
+
+/// A [`RequestBuilder`] with overrides of the host policy, see [`RequestBuilderExt`].
+#[derive(Debug)]
+pub struct BackoffRequestBuilder {
+    builder: RequestBuilder,
+    overrides: RequestOverrides,
+}
+
+impl From<RequestBuilder> for BackoffRequestBuilder {
+    fn from(builder: RequestBuilder) -> Self {
+        Self {
+            builder,
+            overrides: RequestOverrides::default(),
+        }
+    }
+}
+
+impl RequestBuilderExt for BackoffRequestBuilder {
+    fn send_with_backoff(self, client: &ReqwestClient) -> BoxFuture<'_, Result<Response>> {
+        Box::pin(async move {
+            let request = self.builder.build().map_err(ReqwestBackoffError::Build)?;
+            client
+                .execute_with_overrides(request, &self.overrides, &client.sender())
+                .await
+        })
+    }
+
+    fn max_attempts(mut self, max_attempts: u32) -> BackoffRequestBuilder {
+        self.overrides.max_attempts = Some(max_attempts);
+        self
+    }
+
+    fn no_retry(mut self) -> BackoffRequestBuilder {
+        self.overrides.no_retry = true;
+        self
+    }
+
+    fn policy(mut self, policy: impl BackoffPolicy + 'static) -> BackoffRequestBuilder {
+        self.overrides.policy = Some(Arc::new(policy));
+        self
+    }
+
+    fn deadline(mut self, deadline: Duration) -> BackoffRequestBuilder {
+        self.overrides.deadline = Some(deadline);
+        self
+    }
+
+    fn retryable_statuses(
+        mut self,
+        statuses: impl IntoIterator<Item = StatusCode>,
+    ) -> BackoffRequestBuilder {
+        self.overrides.retryable_statuses = Some(statuses.into_iter().collect());
+        self
+    }
+
+    fn overrides(mut self, overrides: RequestOverrides) -> BackoffRequestBuilder {
+        self.overrides = overrides;
+        self
+    }
+}
+
+impl RequestBuilderExt for RequestBuilder {
+    fn send_with_backoff(self, client: &ReqwestClient) -> BoxFuture<'_, Result<Response>> {
+        BackoffRequestBuilder::from(self).send_with_backoff(client)
+    }
+
+    fn max_attempts(self, max_attempts: u32) -> BackoffRequestBuilder {
+        BackoffRequestBuilder::from(self).max_attempts(max_attempts)
+    }
+
+    fn no_retry(self) -> BackoffRequestBuilder {
+        BackoffRequestBuilder::from(self).no_retry()
+    }
+
+    fn policy(self, policy: impl BackoffPolicy + 'static) -> BackoffRequestBuilder {
+        BackoffRequestBuilder::from(self).policy(policy)
+    }
+
+    fn deadline(self, deadline: Duration) -> BackoffRequestBuilder {
+        BackoffRequestBuilder::from(self).deadline(deadline)
+    }
+
+    fn retryable_statuses(
+        self,
+        statuses: impl IntoIterator<Item = StatusCode>,
+    ) -> BackoffRequestBuilder {
+        BackoffRequestBuilder::from(self).retryable_statuses(statuses)
+    }
+
+    fn overrides(self, overrides: RequestOverrides) -> BackoffRequestBuilder {
+        BackoffRequestBuilder::from(self).overrides(overrides)
+    }
+}
@@ -0,0 +1,174 @@
+//! Calls made of several requests, like a download that resumes with a `Range` request when the
+//! connection drops while the body is read.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use reqwest::{Request, Response};
+
+use crate::body::RequestSource;
+use crate::config::is_caused_by_io_error;
+use crate::error::{AttemptOutcome, AttemptRecord};
+use crate::idempotency;
+use crate::policy::{BackoffPolicy, RetryState};
+use crate::prelude::*;
+use crate::request::take_overrides;
+use crate::{summarize_result, CallState, ReqwestClient};
+
+/// Returns `true` if reading a body failed because the connection dropped or stalled, as opposed
+/// to a body that can't be decoded.
+pub(crate) fn is_interrupted_body(error: &reqwest::Error) -> bool {
+    error.is_timeout() || is_caused_by_io_error(error)
+}
+
+/// The state of a call that sends several requests, which share the policy's attempts and the
+/// maximum elapsed time of the client.
+pub(crate) struct ResumableCall<'a> {
+    client: &'a ReqwestClient,
+    policy: Arc<dyn BackoffPolicy>,
+    /// Fail with the first interruption as is, see
+    /// [`RequestOverrides::no_retry`](crate::RequestOverrides::no_retry).
+    no_retry: bool,
+    /// Whether the call may be resumed after the server received a request, see
+    /// [`UnsafeRetries`](crate::UnsafeRetries).
+    idempotent: bool,
+    state: CallState,
+    previous_delay: Option<Duration>,
+    history: Vec<AttemptRecord>,
+}
+
+impl<'a> ResumableCall<'a> {
+    /// Starts a call with the first `request`, adding an `Idempotency-Key` to it if configured.
+    /// Later requests of the call have to be created from it to keep the key.
+    pub(crate) fn new(
+        client: &'a ReqwestClient,
+        policy: Arc<dyn BackoffPolicy>,
+        request: &mut Request,
+    ) -> Self {
+        Self {
+            idempotent: idempotency::prepare(request, client.unsafe_retries),
+            state: client.start_call(None),
+            client,
+            policy,
+            no_retry: false,
+            previous_delay: None,
+            history: Vec::new(),
+        }
+    }
+
+    /// Like [`new`](Self::new), with the policy for the host of `request` and the
+    /// [`RequestOverrides`](crate::RequestOverrides) in its extensions. Returns the request
+    /// without the overrides.
+    pub(crate) fn with_overrides(
+        client: &'a ReqwestClient,
+        request: Request,
+    ) -> Result<(Self, Request)> {
+        let (mut request, overrides) = take_overrides(request)?;
+        let policy = overrides.apply(client.get_policy_for_request(&request));
+        let mut call = Self::new(client, policy, &mut request);
+        call.state = client.start_call(overrides.deadline);
+        call.no_retry = overrides.no_retry;
+        Ok((call, request))
+    }
+
+    /// Sends the next request of the call with backoff, or once if it can't be cloned.
+    pub(crate) async fn execute(&mut self, request: Request) -> Result<Response> {
+        self.execute_source(RequestSource::new(request)).await
+    }
+
+    /// The number of the current attempt, counted across all requests of the call.
+    pub(crate) fn attempt(&self) -> u32 {
+        self.state.attempt
+    }
+
+    pub(crate) async fn execute_source(&mut self, request: RequestSource) -> Result<Response> {
+        let send = self.client.sender();
+        self.client
+            .execute_attempts(request, &self.policy, &send, &mut self.state)
+            .await
+    }
+
+    /// Waits before the next request after `result` failed the call, or fails once the policy's
+    /// attempts or the maximum elapsed time are used up.
+    ///
+    /// Calls with methods that are not idempotent fail with `result` instead, unless it shows that
+    /// the server didn't act on the request.
+    pub(crate) async fn wait(&mut self, result: reqwest::Result<Response>) -> Result<()> {
+        if self.no_retry {
+            return Err(into_error(result).await);
+        }
+        if !self.idempotent && !idempotency::failed_before_processing(&result, None) {
+            info!("Not resuming the call, the server may have acted on it already");
+            return Err(into_error(result).await);
+        }
+        let elapsed = self.client.elapsed_since(self.state.start);
+        let mut record = AttemptRecord {
+            attempt: self.state.attempt,
+            outcome: match &result {
+                Ok(response) => AttemptOutcome::Status(response.status()),
+                Err(error) => AttemptOutcome::Error(error.to_string()),
+            },
+            elapsed,
+            delay: None,
+        };
+        if self.state.attempt >= self.policy.max_attempts() {
+            self.history.push(record);
+            let (last_response, last_error) = summarize_result(result).await;
+            return Err(ReqwestBackoffError::BackoffExceeded {
+                backoff_attempts: self.state.attempt,
+                elapsed,
+                history: std::mem::take(&mut self.history),
+                last_response,
+                last_error,
+            });
+        }
+        let state = RetryState::new(
+            self.state.attempt,
+            self.previous_delay,
+            self.client.clock.now(),
+        );
+        let delay = match &result {
+            Ok(response) => self.policy.backoff_time(response, &state)?,
+            Err(error) => self.policy.error_backoff_time(error, &state),
+        };
+        if let Some(max_elapsed) = self.state.max_elapsed {
+            if elapsed + delay > max_elapsed {
+                warn!(
+                    "Resuming would exceed the maximum elapsed time of {:?}",
+                    max_elapsed
+                );
+                self.history.push(record);
+                let (last_response, last_error) = summarize_result(result).await;
+                return Err(ReqwestBackoffError::DeadlineExceeded {
+                    elapsed,
+                    attempts: self.state.attempt,
+                    history: std::mem::take(&mut self.history),
+                    last_response,
+                    last_error,
+                });
+            }
+        }
+        record.delay = Some(delay);
+        self.history.push(record);
+        match &result {
+            Ok(response) => {
+                warn!(wait = ?delay, "Call failed with {}, resuming after the delay", response.status())
+            }
+            Err(error) => {
+                warn!(wait = ?delay, "Call failed with {}, resuming after the delay", error)
+            }
+        }
+        self.client.sleeper.sleep(delay).await;
+        self.previous_delay = Some(delay);
+        self.state.attempt += 1;
+        Ok(())
+    }
+}
+
+/// The error for a call that ends with `result` without resuming.
+async fn into_error(result: reqwest::Result<Response>) -> ReqwestBackoffError {
+    match result {
+        Ok(response) => ReqwestBackoffError::unexpected_status(response, "resuming a call").await,
+        Err(error) => error.into(),
+    }
+}
@@ -0,0 +1,338 @@
+//! A local HTTP server that replies with a scripted sequence of responses, for testing retry
+//! behavior without network access.
+//!
+//! The server listens on `127.0.0.1`, so the client needs a policy for that host to exercise the
+//! Twitch or Google handling:
+//!
+//! ```no_run
+//! # use twba_reqwest_backoff::testing::{scenarios, MockServer};
+//! # use twba_reqwest_backoff::{GooglePolicy, ReqwestClient};
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let server = MockServer::start(scenarios::google_rate_limit_exceeded()).await?;
+//! let client = ReqwestClient::builder()
+//!     .policy("127.0.0.1", GooglePolicy::default())
+//!     .build()?;
+//! let response = client
+//!     .execute_with_backoff(client.get(server.url("/upload")).build()?)
+//!     .await?;
+//! assert_eq!(response.status(), 200);
+//! assert_eq!(server.received_requests().len(), 2);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+
+use bytes::Bytes;
+use http_body_util::{BodyExt, Either, Full};
+use hyper::body::Frame;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper_util::rt::TokioIo;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Method, StatusCode};
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+use url::Url;
+
+/// A response the [`MockServer`] replies with.
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    delay: Option<Duration>,
+    truncated: bool,
+}
+
+impl MockResponse {
+    /// # Panics
+    ///
+    /// Panics if `status` is not a valid status code.
+    pub fn new(status: u16) -> Self {
+        Self {
+            status: StatusCode::from_u16(status).expect("invalid status code"),
+            headers: HeaderMap::new(),
+            body: Bytes::new(),
+            delay: None,
+            truncated: false,
+        }
+    }
+
+    /// A `200 OK` without a body.
+    pub fn ok() -> Self {
+        Self::new(200)
+    }
+
+    /// # Panics
+    ///
+    /// Panics if `name` or `value` are not valid in a header.
+    pub fn header(mut self, name: &str, value: impl ToString) -> Self {
+        let name = HeaderName::try_from(name).expect("invalid header name");
+        let value = HeaderValue::try_from(value.to_string()).expect("invalid header value");
+        self.headers.append(name, value);
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Bytes>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Sets `value` as the body, along with a JSON content type.
+    pub fn json(self, value: &serde_json::Value) -> Self {
+        self.header("Content-Type", "application/json")
+            .body(value.to_string())
+    }
+
+    /// Sends only the first `len` bytes of the body and then closes the connection, to simulate a
+    /// connection that drops in the middle of the body.
+    pub fn truncate_body(mut self, len: usize) -> Self {
+        let full_len = self.body.len();
+        self.body.truncate(len);
+        self.truncated = true;
+        self.header("Content-Length", full_len)
+    }
+
+    /// Waits (in real time) before sending the response, for example to trigger timeouts.
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = Some(delay);
+        self
+    }
+}
+
+/// A request the [`MockServer`] received.
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: Method,
+    /// The path including the query, if any.
+    pub path: String,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+#[derive(Debug, Default)]
+struct ServerState {
+    responses: VecDeque<MockResponse>,
+    requests: Vec<RecordedRequest>,
+}
+
+/// A local HTTP server replying with a scripted sequence of responses.
+///
+/// Each request gets the next response of the sequence. Once the sequence is used up, requests
+/// get a `500` response. The server stops when it is dropped.
+#[derive(Debug)]
+pub struct MockServer {
+    addr: SocketAddr,
+    state: Arc<Mutex<ServerState>>,
+    handle: JoinHandle<()>,
+}
+
+impl MockServer {
+    /// Starts a server on a random local port.
+    pub async fn start(responses: impl IntoIterator<Item = MockResponse>) -> std::io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(Mutex::new(ServerState {
+            responses: responses.into_iter().collect(),
+            requests: Vec::new(),
+        }));
+
+        let server_state = state.clone();
+        let handle = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let state = server_state.clone();
+                let service = service_fn(move |request| respond(state.clone(), request));
+                tokio::spawn(async move {
+                    // Errors only mean that the client went away, which is fine for a test server
+                    let _ = http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+
+        Ok(Self {
+            addr,
+            state,
+            handle,
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The URL for `path` on this server.
+    pub fn url(&self, path: &str) -> Url {
+        Url::parse(&format!("http://{}", self.addr))
+            .and_then(|base| base.join(path))
+            .expect("invalid path")
+    }
+
+    /// Appends responses to the end of the sequence.
+    pub fn push_responses(&self, responses: impl IntoIterator<Item = MockResponse>) {
+        self.state.lock().unwrap().responses.extend(responses);
+    }
+
+    /// All requests received so far, in order.
+    pub fn received_requests(&self) -> Vec<RecordedRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    /// The number of scripted responses that have not been sent yet.
+    pub fn remaining_responses(&self) -> usize {
+        self.state.lock().unwrap().responses.len()
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+async fn respond(
+    state: Arc<Mutex<ServerState>>,
+    request: hyper::Request<hyper::body::Incoming>,
+) -> Result<hyper::Response<Either<Full<Bytes>, TruncatedBody>>, Infallible> {
+    let (parts, body) = request.into_parts();
+    let body = body
+        .collect()
+        .await
+        .map(|collected| collected.to_bytes())
+        .unwrap_or_default();
+    let path = parts
+        .uri
+        .path_and_query()
+        .map_or_else(|| parts.uri.path().to_string(), |p| p.to_string());
+
+    let scripted = {
+        let mut state = state.lock().unwrap();
+        state.requests.push(RecordedRequest {
+            method: parts.method,
+            path,
+            headers: parts.headers,
+            body,
+        });
+        state.responses.pop_front()
+    };
+    let scripted = scripted.unwrap_or_else(|| {
+        MockResponse::new(500).body("The mock server has no scripted responses left")
+    });
+
+    if let Some(delay) = scripted.delay {
+        tokio::time::sleep(delay).await;
+    }
+    let body = if scripted.truncated {
+        Either::Right(TruncatedBody::new(scripted.body))
+    } else {
+        Either::Left(Full::new(scripted.body))
+    };
+    let mut response = hyper::Response::new(body);
+    *response.status_mut() = scripted.status;
+    *response.headers_mut() = scripted.headers;
+    Ok(response)
+}
+
+/// Sends its data and then fails, which makes the server close the connection.
+///
+/// Waits briefly before failing, so the data is flushed to the client first.
+struct TruncatedBody {
+    data: Option<Bytes>,
+    flush: Pin<Box<tokio::time::Sleep>>,
+}
+
+impl TruncatedBody {
+    fn new(data: Bytes) -> Self {
+        Self {
+            data: Some(data),
+            flush: Box::pin(tokio::time::sleep(Duration::from_millis(20))),
+        }
+    }
+}
+
+impl hyper::body::Body for TruncatedBody {
+    type Data = Bytes;
+    type Error = std::io::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, std::io::Error>>> {
+        if let Some(data) = self.data.take() {
+            return Poll::Ready(Some(Ok(Frame::data(data))));
+        }
+        ready!(self.flush.as_mut().poll(cx));
+        Poll::Ready(Some(Err(std::io::ErrorKind::ConnectionReset.into())))
+    }
+}
+
+/// Response sequences for common rate limit situations.
+pub mod scenarios {
+    use chrono::{DateTime, Utc};
+    use serde_json::json;
+
+    use super::MockResponse;
+
+    /// A Twitch `429` with the `Ratelimit-*` headers for an empty bucket.
+    pub fn twitch_rate_limit_response(reset: DateTime<Utc>) -> MockResponse {
+        MockResponse::new(429)
+            .header("Ratelimit-Limit", 800)
+            .header("Ratelimit-Remaining", 0)
+            .header("Ratelimit-Reset", reset.timestamp())
+    }
+
+    /// A Google error response with the given status and `reason`.
+    pub fn google_error_response(status: u16, reason: &str) -> MockResponse {
+        MockResponse::new(status).json(&json!({
+            "error": {
+                "code": status,
+                "message": format!("Mock error: {}", reason),
+                "errors": [{
+                    "domain": "usageLimits",
+                    "reason": reason,
+                    "message": format!("Mock error: {}", reason),
+                }],
+            }
+        }))
+    }
+
+    /// Twitch rate limits the first request until `reset`, then the retry succeeds.
+    pub fn twitch_rate_limited(reset: DateTime<Utc>) -> Vec<MockResponse> {
+        vec![twitch_rate_limit_response(reset), MockResponse::ok()]
+    }
+
+    /// Google rejects the first request with `rateLimitExceeded`, then the retry succeeds.
+    pub fn google_rate_limit_exceeded() -> Vec<MockResponse> {
+        vec![
+            google_error_response(403, "rateLimitExceeded"),
+            MockResponse::ok(),
+        ]
+    }
+
+    /// Google rejects the request with `quotaExceeded`, which must not be retried.
+    pub fn google_quota_exceeded() -> Vec<MockResponse> {
+        vec![
+            google_error_response(403, "quotaExceeded"),
+            MockResponse::ok(),
+        ]
+    }
+
+    /// A `429` and a `503`, both with `Retry-After`, before the request succeeds.
+    pub fn retry_after(seconds: u64) -> Vec<MockResponse> {
+        vec![
+            MockResponse::new(429).header("Retry-After", seconds),
+            MockResponse::new(503).header("Retry-After", seconds),
+            MockResponse::ok(),
+        ]
+    }
+}
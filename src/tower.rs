@@ -0,0 +1,119 @@
+//! A [`tower`](https://docs.rs/tower) layer that applies the backoff of a [`ReqwestClient`] to any
+//! service handling [`reqwest::Request`]s.
+//!
+//! ```no_run
+//! # use twba_reqwest_backoff::tower::BackoffLayer;
+//! # use twba_reqwest_backoff::ReqwestClient;
+//! # use tower_layer::Layer;
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let client = ReqwestClient::builder().build()?;
+//! let service = BackoffLayer::new(client).layer(reqwest::Client::new());
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Only errors of the inner service that are [`reqwest::Error`]s are retried. Errors of other
+//! layers below this one, like the `Elapsed` of tower's timeout layer or the `Overloaded` of its
+//! load-shed layer, end the call. For a timeout per attempt, use
+//! [`attempt_timeout`](crate::ReqwestClientBuilder::attempt_timeout) instead.
+
+use std::future::poll_fn;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+
+use reqwest::{Request, Response};
+use tower_layer::Layer;
+use tower_service::Service;
+use tracing::Instrument;
+
+use crate::clock::BoxFuture;
+use crate::prelude::*;
+use crate::request::take_overrides;
+use crate::ReqwestClient;
+
+/// Wraps services in a [`BackoffService`].
+///
+/// The policies, rate limits, circuit breakers, hooks and token providers of the client are used,
+/// and shared with the client and its clones. The wrapped [`reqwest::Client`] is not used.
+#[derive(Debug, Clone)]
+pub struct BackoffLayer {
+    client: ReqwestClient,
+}
+
+impl BackoffLayer {
+    pub fn new(client: ReqwestClient) -> Self {
+        Self { client }
+    }
+}
+
+impl<S> Layer<S> for BackoffLayer {
+    type Service = BackoffService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        BackoffService {
+            inner,
+            client: self.client.clone(),
+        }
+    }
+}
+
+/// Sends requests through the inner service with the backoff of a [`ReqwestClient`], see
+/// [`execute_with_backoff`](ReqwestClient::execute_with_backoff).
+///
+/// Every attempt uses a clone of the inner service. Errors of the inner service that are
+/// [`reqwest::Error`]s are retried like transport errors. Other errors, like those of tower's
+/// timeout or load-shed layers, are not retried and fail with [`ReqwestBackoffError::Other`].
+#[derive(Debug, Clone)]
+pub struct BackoffService<S> {
+    inner: S,
+    client: ReqwestClient,
+}
+
+impl<S> Service<Request> for BackoffService<S>
+where
+    S: Service<Request, Response = Response> + Clone + Send + 'static,
+    S::Error: Into<Box<dyn StdError + Send + Sync>>,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = ReqwestBackoffError;
+    type Future = BoxFuture<'static, Result<Response>>;
+
+    /// Always ready, since the readiness of the inner service is checked for every attempt.
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let client = self.client.clone();
+        let inner = Mutex::new(self.inner.clone());
+        let span = tracing::info_span!("backoff_service", url = %request.url());
+        let future = async move {
+            let send = move |request: Request| -> BoxFuture<'static, _> {
+                let mut service = inner.lock().unwrap().clone();
+                Box::pin(async move {
+                    if let Err(error) = poll_fn(|cx| service.poll_ready(cx)).await {
+                        return into_reqwest_error(error).map(Err);
+                    }
+                    match service.call(request).await {
+                        Ok(response) => Ok(Ok(response)),
+                        Err(error) => into_reqwest_error(error).map(Err),
+                    }
+                })
+            };
+            let (request, overrides) = take_overrides(request)?;
+            client
+                .execute_with_overrides(request, &overrides, &send)
+                .await
+        };
+        Box::pin(future.instrument(span))
+    }
+}
+
+/// Returns `Ok` with the error if it is a [`reqwest::Error`], which may be retried.
+fn into_reqwest_error(error: impl Into<Box<dyn StdError + Send + Sync>>) -> Result<reqwest::Error> {
+    match error.into().downcast::<reqwest::Error>() {
+        Ok(error) => Ok(*error),
+        Err(error) => Err(ReqwestBackoffError::Other(error)),
+    }
+}
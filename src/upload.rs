@@ -0,0 +1,313 @@
+//! Resumable uploads to Google APIs, see
+//! <https://developers.google.com/youtube/v3/guides/using_resumable_upload_protocol>.
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use reqwest::header::{HeaderValue, CONTENT_LENGTH, CONTENT_RANGE, LOCATION, RANGE};
+use reqwest::{Request, Response};
+use url::Url;
+
+use crate::body::{BodySource, RequestSource};
+use crate::policy::{BackoffPolicy, RetryState};
+use crate::prelude::*;
+use crate::resume::ResumableCall;
+use crate::ReqwestClient;
+
+/// Chunks have to be a multiple of this size, except for the last one.
+pub const CHUNK_GRANULARITY: u64 = 256 * 1024;
+const DEFAULT_CHUNK_SIZE: u64 = 32 * CHUNK_GRANULARITY;
+
+/// Google uses `308 Resume Incomplete` for partially uploaded files.
+const RESUME_INCOMPLETE: u16 = 308;
+
+/// How much of an upload the server has committed.
+#[derive(Debug)]
+pub enum UploadStatus {
+    /// The server has the first `committed` bytes.
+    Incomplete { committed: u64 },
+    /// The upload is finished, with the final response of the API.
+    Complete(Response),
+}
+
+/// A resumable upload session.
+///
+/// Uploads the file in chunks. If a chunk fails with a `5xx` response or a transport error, the
+/// committed offset is queried from the server and the upload resumes from there. These failures
+/// are limited and delayed by the policy for the session URL, like any other retry, and the
+/// chunks go through the rate limits, circuit breakers, hooks and token providers of the client.
+#[derive(Debug, Clone)]
+pub struct ResumableUpload {
+    session_url: Url,
+    total: u64,
+    chunk_size: u64,
+}
+
+impl ResumableUpload {
+    /// Continues an existing session, for example one started by a previous run.
+    pub fn from_session(session_url: Url, total: u64) -> Self {
+        Self {
+            session_url,
+            total,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        }
+    }
+
+    /// The URL of the session, which can be stored to resume the upload later.
+    pub fn session_url(&self) -> &Url {
+        &self.session_url
+    }
+
+    /// The total size of the upload in bytes.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// The size of the chunks, rounded down to a multiple of [`CHUNK_GRANULARITY`].
+    pub fn with_chunk_size(mut self, chunk_size: u64) -> Self {
+        self.chunk_size = (chunk_size / CHUNK_GRANULARITY).max(1) * CHUNK_GRANULARITY;
+        self
+    }
+
+    /// Asks the server how much of the upload it has committed.
+    #[tracing::instrument(skip(client))]
+    pub async fn query_status(&self, client: &ReqwestClient) -> Result<UploadStatus> {
+        let response = client
+            .execute_with_backoff(self.status_request(client)?)
+            .await?;
+        self.upload_status(response, "querying the upload status")
+            .await
+    }
+
+    /// Uploads the file at `path`, starting at the offset the server has committed.
+    ///
+    /// All requests of the upload share the policy's attempts and the maximum elapsed time of the
+    /// client. After a failed chunk, the retry asks for the committed offset instead of sending
+    /// the chunk again, and the upload continues from there. A chunk that doesn't move the
+    /// committed offset forward counts as a failed attempt.
+    #[tracing::instrument(skip(client))]
+    pub async fn upload_file(&self, client: &ReqwestClient, path: &Path) -> Result<Response> {
+        let mut status_request = self.status_request(client)?;
+        let policy = UploadPolicy(client.get_policy_for_request(&status_request).clone());
+        let mut call = ResumableCall::new(client, Arc::new(policy), &mut status_request);
+        let response = call.execute(status_request).await?;
+        let mut offset = match self
+            .upload_status(response, "querying the upload status")
+            .await?
+        {
+            UploadStatus::Incomplete { committed } => committed,
+            UploadStatus::Complete(response) => return Ok(response),
+        };
+        loop {
+            let len = self.chunk_size.min(self.total - offset);
+            info!(
+                "Uploading bytes {}..{} of {}",
+                offset,
+                offset + len,
+                self.total
+            );
+            let request = self.chunk_request(client, offset, len)?;
+            let body = BodySource::file_range(path, offset, len);
+            let request =
+                RequestSource::with_body(request, body).retry_with(self.status_request(client)?);
+            let attempt = call.attempt();
+            let response = call.execute_source(request).await?;
+            if response.status().is_success() {
+                return Ok(response);
+            }
+            if response.status().as_u16() != RESUME_INCOMPLETE {
+                return Err(
+                    ReqwestBackoffError::unexpected_status(response, "uploading a chunk").await,
+                );
+            }
+            let committed = self.committed_offset(&response)?;
+            // After a retry, this is the response to the status query
+            if committed <= offset && call.attempt() == attempt {
+                warn!(offset, "The server didn't commit any bytes of the chunk");
+                call.wait(Ok(response)).await?;
+            }
+            offset = committed;
+        }
+    }
+
+    fn status_request(&self, client: &ReqwestClient) -> Result<Request> {
+        Ok(client
+            .put(self.session_url.clone())
+            .header(CONTENT_LENGTH, 0)
+            .header(CONTENT_RANGE, format!("bytes */{}", self.total))
+            .build()?)
+    }
+
+    /// The request for `len` bytes from `offset`, without the body. Without any bytes left, this
+    /// finishes the upload, which is needed for empty files.
+    fn chunk_request(&self, client: &ReqwestClient, offset: u64, len: u64) -> Result<Request> {
+        if len == 0 {
+            return self.status_request(client);
+        }
+        Ok(client
+            .put(self.session_url.clone())
+            .header(CONTENT_LENGTH, len)
+            .header(
+                CONTENT_RANGE,
+                format!("bytes {}-{}/{}", offset, offset + len - 1, self.total),
+            )
+            .build()?)
+    }
+
+    async fn upload_status(
+        &self,
+        response: Response,
+        context: &'static str,
+    ) -> Result<UploadStatus> {
+        if response.status().as_u16() == RESUME_INCOMPLETE {
+            let committed = self.committed_offset(&response)?;
+            Ok(UploadStatus::Incomplete { committed })
+        } else if response.status().is_success() {
+            Ok(UploadStatus::Complete(response))
+        } else {
+            Err(ReqwestBackoffError::unexpected_status(response, context).await)
+        }
+    }
+
+    /// Reads the number of committed bytes from the `Range` header of a `308` response.
+    fn committed_offset(&self, response: &Response) -> Result<u64> {
+        let Some(range) = response.headers().get(RANGE) else {
+            return Ok(0);
+        };
+        let invalid = |reason: &str| ReqwestBackoffError::InvalidHeader {
+            name: RANGE.to_string(),
+            value: String::from_utf8_lossy(range.as_bytes()).into_owned(),
+            reason: reason.to_string(),
+        };
+        let end = range
+            .to_str()
+            .ok()
+            .and_then(|range| range.strip_prefix("bytes=0-"))
+            .ok_or_else(|| invalid("expected bytes=0-<end>"))?
+            .parse::<u64>()
+            .map_err(|e| invalid(&e.to_string()))?;
+        if end >= self.total {
+            return Err(invalid(&format!(
+                "the upload only has {} bytes",
+                self.total
+            )));
+        }
+        Ok(end + 1)
+    }
+}
+
+/// Retries all `5xx` responses of an upload, in addition to what the policy for the session
+/// retries, since the upload resumes from the committed offset anyway.
+#[derive(Debug)]
+struct UploadPolicy(Arc<dyn BackoffPolicy>);
+
+impl BackoffPolicy for UploadPolicy {
+    fn should_backoff(&self, response: &Response) -> bool {
+        response.status().is_server_error() || self.0.should_backoff(response)
+    }
+
+    fn backoff_time(&self, response: &Response, state: &RetryState) -> Result<Duration> {
+        self.0.backoff_time(response, state)
+    }
+
+    fn max_attempts(&self) -> u32 {
+        self.0.max_attempts()
+    }
+
+    fn should_retry_error(&self, error: &reqwest::Error) -> bool {
+        self.0.should_retry_error(error)
+    }
+
+    fn error_backoff_time(&self, error: &reqwest::Error, state: &RetryState) -> Duration {
+        self.0.error_backoff_time(error, state)
+    }
+
+    fn delay_before_request(&self, request: &Request, now: DateTime<Utc>) -> Option<Duration> {
+        self.0.delay_before_request(request, now)
+    }
+
+    fn needs_body(&self, response: &Response) -> bool {
+        !response.status().is_server_error() && self.0.needs_body(response)
+    }
+
+    fn check_body(&self, response: &Response, body: &[u8]) -> Result<bool> {
+        self.0.check_body(response, body)
+    }
+
+    fn on_response(&self, response: &Response) {
+        self.0.on_response(response)
+    }
+}
+
+impl ReqwestClient {
+    /// Starts a resumable upload session for `total` bytes of `content_type`.
+    ///
+    /// `request` is the initial request to the upload endpoint, including `uploadType=resumable`,
+    /// authentication and the metadata body. It is sent with backoff.
+    #[tracing::instrument(skip(self))]
+    pub async fn start_resumable_upload(
+        &self,
+        mut request: Request,
+        content_type: &str,
+        total: u64,
+    ) -> Result<ResumableUpload> {
+        let content_type = HeaderValue::try_from(content_type).map_err(|e| {
+            ReqwestBackoffError::InvalidHeader {
+                name: "X-Upload-Content-Type".to_string(),
+                value: content_type.to_string(),
+                reason: e.to_string(),
+            }
+        })?;
+        let headers = request.headers_mut();
+        headers.insert("X-Upload-Content-Type", content_type);
+        headers.insert("X-Upload-Content-Length", HeaderValue::from(total));
+
+        let response = self.execute_with_backoff(request).await?;
+        if !response.status().is_success() {
+            return Err(ReqwestBackoffError::unexpected_status(
+                response,
+                "starting a resumable upload",
+            )
+            .await);
+        }
+        let location = response
+            .headers()
+            .get(LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .ok_or_else(|| ReqwestBackoffError::InvalidHeader {
+                name: LOCATION.to_string(),
+                value: String::new(),
+                reason: "missing session URL".to_string(),
+            })?;
+        let session_url =
+            response
+                .url()
+                .join(location)
+                .map_err(|e| ReqwestBackoffError::InvalidHeader {
+                    name: LOCATION.to_string(),
+                    value: location.to_string(),
+                    reason: e.to_string(),
+                })?;
+        info!("Started resumable upload session");
+        Ok(ResumableUpload::from_session(session_url, total))
+    }
+
+    /// Uploads the file at `path` with the resumable upload protocol.
+    ///
+    /// See [`start_resumable_upload`](Self::start_resumable_upload) for `request`.
+    pub async fn upload_file_resumable(
+        &self,
+        request: Request,
+        path: impl AsRef<Path>,
+        content_type: &str,
+    ) -> Result<Response> {
+        let path = path.as_ref();
+        let total = tokio::fs::metadata(path).await?.len();
+        let upload = self
+            .start_resumable_upload(request, content_type, total)
+            .await?;
+        upload.upload_file(self, path).await
+    }
+}
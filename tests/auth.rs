@@ -0,0 +1,105 @@
+mod common;
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+use common::{get, mock_client};
+use twba_reqwest_backoff::clock::BoxFuture;
+use twba_reqwest_backoff::testing::{MockResponse, MockServer};
+use twba_reqwest_backoff::{BackoffConfig, DefaultPolicy, ReqwestBackoffError, TokenProvider};
+
+#[derive(Debug, Clone, Default)]
+struct CountingProvider {
+    refreshes: Arc<AtomicU32>,
+}
+
+impl TokenProvider for CountingProvider {
+    fn token(&self) -> BoxFuture<'_, Result<String, ReqwestBackoffError>> {
+        Box::pin(async { Ok("expired".to_string()) })
+    }
+
+    fn refresh(&self) -> BoxFuture<'_, Result<String, ReqwestBackoffError>> {
+        let refreshes = self.refreshes.fetch_add(1, Ordering::SeqCst) + 1;
+        Box::pin(async move { Ok(format!("fresh-{}", refreshes)) })
+    }
+}
+
+fn authorization(server: &MockServer) -> Vec<String> {
+    server
+        .received_requests()
+        .iter()
+        .map(|request| {
+            request.headers["authorization"]
+                .to_str()
+                .unwrap()
+                .to_string()
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn refreshes_the_token_once_on_401() {
+    let server = MockServer::start([MockResponse::new(401), MockResponse::ok()])
+        .await
+        .unwrap();
+    let provider = CountingProvider::default();
+    let config = BackoffConfig::default().with_max_attempts(1);
+    let (builder, clock) = mock_client(DefaultPolicy::new(config));
+    let client = builder
+        .token_provider("127.0.0.1", provider.clone())
+        .build()
+        .unwrap();
+
+    let response = get(&client, &server).await.unwrap();
+
+    assert_eq!(response.status(), 200);
+    assert_eq!(authorization(&server), ["Bearer expired", "Bearer fresh-1"]);
+    assert!(clock.sleeps().is_empty());
+}
+
+#[tokio::test]
+async fn returns_the_second_401() {
+    let server = MockServer::start([MockResponse::new(401), MockResponse::new(401)])
+        .await
+        .unwrap();
+    let provider = CountingProvider::default();
+    let (builder, _clock) = mock_client(DefaultPolicy::default());
+    let client = builder
+        .token_provider("127.0.0.1", provider.clone())
+        .build()
+        .unwrap();
+
+    let response = get(&client, &server).await.unwrap();
+
+    assert_eq!(response.status(), 401);
+    assert_eq!(provider.refreshes.load(Ordering::SeqCst), 1);
+    assert_eq!(server.received_requests().len(), 2);
+}
+
+#[tokio::test]
+async fn request_that_cant_be_cloned_gets_the_token() {
+    let server = MockServer::start([MockResponse::new(503), MockResponse::ok()])
+        .await
+        .unwrap();
+    let (builder, clock) = mock_client(DefaultPolicy::default());
+    let client = builder
+        .token_provider("127.0.0.1", CountingProvider::default())
+        .build()
+        .unwrap();
+
+    let chunks: Vec<Result<&'static [u8], std::io::Error>> = vec![Ok(b"0123"), Ok(b"4567")];
+    let request = client
+        .put(server.url("/upload"))
+        .body(reqwest::Body::wrap_stream(futures_util::stream::iter(
+            chunks,
+        )))
+        .build()
+        .unwrap();
+    let response = client.execute_with_backoff(request).await.unwrap();
+
+    // Sent once, since the body can't be sent again
+    assert_eq!(response.status(), 503);
+    assert_eq!(authorization(&server), ["Bearer expired"]);
+    assert_eq!(server.received_requests()[0].body.as_ref(), b"01234567");
+    assert!(clock.sleeps().is_empty());
+}
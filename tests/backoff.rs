@@ -0,0 +1,413 @@
+mod common;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use common::{get, mock_client};
+use reqwest::StatusCode;
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpListener;
+use twba_reqwest_backoff::clock::Clock;
+use twba_reqwest_backoff::testing::{scenarios, MockResponse, MockServer};
+use twba_reqwest_backoff::{
+    AttemptOutcome, BackoffConfig, DefaultPolicy, GooglePolicy, ReqwestBackoffError,
+    TransportErrors, TwitchPolicy,
+};
+
+#[tokio::test]
+async fn retry_after_is_honored_for_429_and_503() {
+    let server = MockServer::start(scenarios::retry_after(7)).await.unwrap();
+    let (builder, clock) = mock_client(DefaultPolicy::default());
+    let client = builder.build().unwrap();
+
+    let response = get(&client, &server).await.unwrap();
+
+    assert_eq!(response.status(), 200);
+    assert_eq!(server.received_requests().len(), 3);
+    assert_eq!(clock.sleeps(), vec![Duration::from_secs(7); 2]);
+}
+
+#[tokio::test]
+async fn retry_after_accepts_an_http_date() {
+    let (builder, clock) = mock_client(DefaultPolicy::default());
+    let date = (clock.now() + Duration::from_secs(90)).format("%a, %d %b %Y %H:%M:%S GMT");
+    let server = MockServer::start([
+        MockResponse::new(503).header("Retry-After", date),
+        MockResponse::ok(),
+    ])
+    .await
+    .unwrap();
+    let client = builder.build().unwrap();
+
+    let response = get(&client, &server).await.unwrap();
+
+    assert_eq!(response.status(), 200);
+    assert_eq!(clock.sleeps(), vec![Duration::from_secs(90)]);
+}
+
+#[tokio::test]
+async fn retry_after_is_clamped_to_max_retry_after() {
+    let server = MockServer::start(scenarios::retry_after(7200))
+        .await
+        .unwrap();
+    let config = BackoffConfig::default().with_max_retry_after(Duration::from_secs(60));
+    let (builder, clock) = mock_client(DefaultPolicy::new(config));
+    let client = builder.build().unwrap();
+
+    let response = get(&client, &server).await.unwrap();
+
+    assert_eq!(response.status(), 200);
+    assert_eq!(clock.sleeps(), vec![Duration::from_secs(60); 2]);
+}
+
+#[tokio::test]
+async fn twitch_waits_until_the_rate_limit_resets() {
+    let (builder, clock) = mock_client(TwitchPolicy::default());
+    let reset = clock.now() + Duration::from_secs(30);
+    let server = MockServer::start(scenarios::twitch_rate_limited(reset))
+        .await
+        .unwrap();
+    let client = builder.build().unwrap();
+
+    let response = get(&client, &server).await.unwrap();
+
+    assert_eq!(response.status(), 200);
+    assert_eq!(clock.sleeps(), vec![Duration::from_secs(30)]);
+}
+
+#[tokio::test]
+async fn twitch_without_reset_header_falls_back_to_exponential_backoff() {
+    let server = MockServer::start([MockResponse::new(429), MockResponse::ok()])
+        .await
+        .unwrap();
+    let (builder, clock) = mock_client(TwitchPolicy::default());
+    let client = builder.build().unwrap();
+
+    let response = get(&client, &server).await.unwrap();
+
+    assert_eq!(response.status(), 200);
+    assert_eq!(clock.sleeps(), vec![BackoffConfig::twitch().base_delay]);
+}
+
+/// Sends three requests to a server whose first response leaves `remaining` requests until the
+/// rate limit resets in 30 seconds.
+async fn twitch_requests_with_remaining(policy: TwitchPolicy, remaining: u32) -> Vec<Duration> {
+    let (builder, clock) = mock_client(policy);
+    let reset = clock.now() + Duration::from_secs(30);
+    let server = MockServer::start([
+        MockResponse::ok()
+            .header("Ratelimit-Limit", 800)
+            .header("Ratelimit-Remaining", remaining)
+            .header("Ratelimit-Reset", reset.timestamp()),
+        MockResponse::ok(),
+        MockResponse::ok(),
+    ])
+    .await
+    .unwrap();
+    let client = builder.build().unwrap();
+
+    for _ in 0..3 {
+        let response = get(&client, &server).await.unwrap();
+        assert_eq!(response.status(), 200);
+    }
+    assert_eq!(server.received_requests().len(), 3);
+    clock.sleeps()
+}
+
+#[tokio::test]
+async fn twitch_waits_for_the_reset_before_using_up_the_rate_limit() {
+    let sleeps = twitch_requests_with_remaining(TwitchPolicy::default(), 1).await;
+
+    assert_eq!(sleeps, vec![Duration::from_secs(30)]);
+}
+
+#[tokio::test]
+async fn twitch_keeps_the_reserve_of_the_rate_limit() {
+    let policy = TwitchPolicy::default().with_reserve(2);
+
+    let sleeps = twitch_requests_with_remaining(policy, 3).await;
+
+    assert_eq!(sleeps, vec![Duration::from_secs(30)]);
+}
+
+#[tokio::test]
+async fn google_rate_limit_is_retried() {
+    let server = MockServer::start(scenarios::google_rate_limit_exceeded())
+        .await
+        .unwrap();
+    let (builder, clock) = mock_client(GooglePolicy::default());
+    let client = builder.build().unwrap();
+
+    let response = get(&client, &server).await.unwrap();
+
+    assert_eq!(response.status(), 200);
+    assert_eq!(clock.sleeps(), vec![BackoffConfig::google().base_delay]);
+}
+
+#[tokio::test]
+async fn google_quota_exceeded_fails_immediately() {
+    let server = MockServer::start(scenarios::google_quota_exceeded())
+        .await
+        .unwrap();
+    let (builder, clock) = mock_client(GooglePolicy::default());
+    let client = builder.build().unwrap();
+
+    let error = get(&client, &server).await.unwrap_err();
+
+    assert!(
+        matches!(&error, ReqwestBackoffError::GoogleApi { reason, .. } if reason == "quotaExceeded"),
+        "unexpected error: {:?}",
+        error
+    );
+    assert_eq!(server.received_requests().len(), 1);
+    assert!(clock.sleeps().is_empty());
+}
+
+#[tokio::test]
+async fn gives_up_after_max_attempts() {
+    let server = MockServer::start(vec![MockResponse::new(503); 10])
+        .await
+        .unwrap();
+    let config = BackoffConfig::default().with_max_attempts(2);
+    let (builder, _clock) = mock_client(DefaultPolicy::new(config));
+    let client = builder.build().unwrap();
+
+    let error = get(&client, &server).await.unwrap_err();
+
+    assert!(matches!(
+        error,
+        ReqwestBackoffError::BackoffExceeded {
+            backoff_attempts: 2,
+            ..
+        }
+    ));
+    assert_eq!(server.received_requests().len(), 2);
+}
+
+#[tokio::test]
+async fn giving_up_reports_history_and_last_response() {
+    let responses = [
+        MockResponse::new(503),
+        MockResponse::new(429),
+        MockResponse::new(503).body("still down"),
+    ];
+    let server = MockServer::start(responses).await.unwrap();
+    let config = BackoffConfig::default().with_max_attempts(3);
+    let (builder, _clock) = mock_client(DefaultPolicy::new(config));
+    let client = builder.build().unwrap();
+
+    let error = get(&client, &server).await.unwrap_err();
+
+    let history = error.history().unwrap();
+    let outcomes: Vec<_> = history.iter().map(|record| &record.outcome).collect();
+    assert_eq!(
+        outcomes,
+        [
+            &AttemptOutcome::Status(StatusCode::SERVICE_UNAVAILABLE),
+            &AttemptOutcome::Status(StatusCode::TOO_MANY_REQUESTS),
+            &AttemptOutcome::Status(StatusCode::SERVICE_UNAVAILABLE),
+        ]
+    );
+    let delays: Vec<_> = history.iter().map(|record| record.delay).collect();
+    let delay = Some(config.base_delay);
+    assert_eq!(delays, [delay, delay, None]);
+    let last_response = error.last_response().unwrap();
+    assert_eq!(last_response.status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(last_response.body_snippet, "still down");
+}
+
+#[tokio::test]
+async fn stops_at_the_deadline() {
+    let server = MockServer::start(vec![MockResponse::new(503); 10])
+        .await
+        .unwrap();
+    let config = BackoffConfig::default().with_base_delay(Duration::from_secs(4));
+    let (builder, clock) = mock_client(DefaultPolicy::new(
+        config.with_max_delay(Duration::from_secs(4)),
+    ));
+    let client = builder
+        .max_elapsed(Duration::from_secs(10))
+        .build()
+        .unwrap();
+
+    let error = get(&client, &server).await.unwrap_err();
+
+    match error {
+        ReqwestBackoffError::DeadlineExceeded {
+            elapsed, attempts, ..
+        } => {
+            assert_eq!(elapsed, Duration::from_secs(8));
+            assert_eq!(attempts, 3);
+        }
+        error => panic!("unexpected error: {:?}", error),
+    }
+    assert_eq!(clock.total_slept(), Duration::from_secs(8));
+}
+
+/// The URL of a port on which nothing listens.
+async fn refused_url() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    drop(listener);
+    url
+}
+
+#[tokio::test]
+async fn connect_errors_are_retried() {
+    let config = BackoffConfig::default().with_max_attempts(3);
+    let (builder, clock) = mock_client(DefaultPolicy::new(config));
+    let client = builder.build().unwrap();
+    let request = client.get(refused_url().await).build().unwrap();
+
+    let error = client.execute_with_backoff(request).await.unwrap_err();
+
+    match error {
+        ReqwestBackoffError::BackoffExceeded {
+            backoff_attempts,
+            last_error: Some(last_error),
+            ..
+        } => {
+            assert_eq!(backoff_attempts, 3);
+            assert!(last_error.is_connect(), "{:?}", last_error);
+        }
+        error => panic!("unexpected error: {:?}", error),
+    }
+    assert_eq!(clock.sleeps(), vec![config.base_delay; 2]);
+}
+
+#[tokio::test]
+async fn disabled_transport_errors_are_not_retried() {
+    let config = BackoffConfig::default().with_retry_on(TransportErrors {
+        connect: false,
+        ..TransportErrors::all()
+    });
+    let (builder, clock) = mock_client(DefaultPolicy::new(config));
+    let client = builder.build().unwrap();
+    let request = client.get(refused_url().await).build().unwrap();
+
+    let error = client.execute_with_backoff(request).await.unwrap_err();
+
+    assert!(
+        matches!(&error, ReqwestBackoffError::Reqwest(e) if e.is_connect()),
+        "unexpected error: {:?}",
+        error
+    );
+    assert!(clock.sleeps().is_empty());
+}
+
+/// Accepts connections and closes them after reading the start of the request, which resets them
+/// because the rest of the body is left unread. Counts the connections.
+async fn resetting_server() -> (String, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    let connections = Arc::new(AtomicUsize::new(0));
+    let counter = connections.clone();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            counter.fetch_add(1, Ordering::SeqCst);
+            let mut buffer = [0; 1024];
+            let _ = stream.read(&mut buffer).await;
+            drop(stream);
+        }
+    });
+    (url, connections)
+}
+
+#[tokio::test]
+async fn io_errors_while_writing_the_body_are_retried() {
+    let (url, connections) = resetting_server().await;
+    let config = BackoffConfig::default().with_max_attempts(2);
+    let (builder, clock) = mock_client(DefaultPolicy::new(config));
+    let client = builder.build().unwrap();
+    let request = client.put(url).body(vec![0u8; 16 << 20]).build().unwrap();
+
+    let error = client.execute_with_backoff(request).await.unwrap_err();
+
+    match error {
+        ReqwestBackoffError::BackoffExceeded {
+            backoff_attempts,
+            last_error: Some(last_error),
+            ..
+        } => {
+            assert_eq!(backoff_attempts, 2);
+            assert!(
+                TransportErrors::all().matches(&last_error),
+                "{:?}",
+                last_error
+            );
+            assert!(!TransportErrors::none().matches(&last_error));
+        }
+        error => panic!("unexpected error: {:?}", error),
+    }
+    assert_eq!(connections.load(Ordering::SeqCst), 2);
+    assert_eq!(clock.sleeps(), vec![config.base_delay]);
+}
+
+#[tokio::test]
+async fn slow_attempts_time_out() {
+    let server = MockServer::start([MockResponse::ok().delay(Duration::from_secs(5))])
+        .await
+        .unwrap();
+    let (builder, clock) = mock_client(DefaultPolicy::default());
+    let start = clock.now();
+    let client = builder
+        .attempt_timeout(Duration::from_millis(100))
+        .build()
+        .unwrap();
+
+    let error = get(&client, &server).await.unwrap_err();
+
+    match error {
+        ReqwestBackoffError::AttemptTimeout {
+            timeout,
+            elapsed,
+            attempts,
+            ..
+        } => {
+            assert_eq!(timeout, Duration::from_millis(100));
+            assert_eq!(elapsed, Duration::from_millis(100));
+            assert_eq!(attempts, 1);
+        }
+        error => panic!("unexpected error: {:?}", error),
+    }
+    assert_eq!(clock.now() - start, chrono::Duration::milliseconds(100));
+    assert!(clock.sleeps().is_empty());
+}
+
+#[tokio::test]
+async fn the_deadline_cuts_the_last_attempt_short() {
+    let server = MockServer::start([
+        MockResponse::new(503),
+        MockResponse::ok().delay(Duration::from_secs(5)),
+    ])
+    .await
+    .unwrap();
+    let delay = Duration::from_millis(800);
+    let config = BackoffConfig::default()
+        .with_base_delay(delay)
+        .with_max_delay(delay);
+    let (builder, clock) = mock_client(DefaultPolicy::new(config));
+    let client = builder
+        .max_elapsed(Duration::from_secs(1))
+        .attempt_timeout(Duration::from_secs(30))
+        .build()
+        .unwrap();
+
+    let error = get(&client, &server).await.unwrap_err();
+
+    match error {
+        ReqwestBackoffError::DeadlineExceeded {
+            elapsed,
+            attempts,
+            history,
+            ..
+        } => {
+            assert_eq!(elapsed, Duration::from_secs(1));
+            assert_eq!(attempts, 2);
+            assert_eq!(history.len(), 2);
+        }
+        error => panic!("unexpected error: {:?}", error),
+    }
+    assert_eq!(clock.sleeps(), vec![delay]);
+}
@@ -0,0 +1,55 @@
+mod common;
+
+use common::mock_client;
+use twba_reqwest_backoff::testing::{MockResponse, MockServer};
+use twba_reqwest_backoff::{BodySource, DefaultPolicy};
+
+#[tokio::test]
+async fn file_body_is_sent_again_on_retry() {
+    let path = std::env::temp_dir().join(format!("twba-body-{}.bin", std::process::id()));
+    std::fs::write(&path, b"0123456789").unwrap();
+    let server = MockServer::start([MockResponse::new(503), MockResponse::ok()])
+        .await
+        .unwrap();
+    let (builder, _clock) = mock_client(DefaultPolicy::default());
+    let client = builder.build().unwrap();
+
+    let request = client.put(server.url("/upload")).build().unwrap();
+    let response = client
+        .execute_with_body(request, BodySource::file_from(&path, 4))
+        .await
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(response.status(), 200);
+    let requests = server.received_requests();
+    assert_eq!(requests.len(), 2);
+    for request in requests {
+        assert_eq!(request.body.as_ref(), b"456789");
+        assert_eq!(request.headers["content-length"], "6");
+    }
+}
+
+#[tokio::test]
+async fn streamed_body_from_factory_is_retried() {
+    let server = MockServer::start([MockResponse::new(429), MockResponse::ok()])
+        .await
+        .unwrap();
+    let (builder, _clock) = mock_client(DefaultPolicy::default());
+    let client = builder.build().unwrap();
+    let body = BodySource::factory(|| {
+        let chunks: Vec<Result<&'static str, std::io::Error>> = vec![Ok("streamed "), Ok("body")];
+        reqwest::Body::wrap_stream(futures_util::stream::iter(chunks))
+    });
+
+    let request = client.post(server.url("/")).build().unwrap();
+    let response = client.execute_with_body(request, body).await.unwrap();
+
+    assert_eq!(response.status(), 200);
+    let bodies: Vec<_> = server
+        .received_requests()
+        .into_iter()
+        .map(|request| request.body)
+        .collect();
+    assert_eq!(bodies, ["streamed body", "streamed body"]);
+}
@@ -0,0 +1,139 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{get, mock_client};
+use twba_reqwest_backoff::testing::{MockResponse, MockServer};
+use twba_reqwest_backoff::{
+    BodySource, CircuitBreakerConfig, CircuitState, DefaultPolicy, ReqwestBackoffError,
+};
+
+#[tokio::test]
+async fn breaker_opens_fails_fast_and_closes_after_trial() {
+    let server = MockServer::start(vec![MockResponse::new(503); 4])
+        .await
+        .unwrap();
+    let (builder, clock) = mock_client(DefaultPolicy::default());
+    let config = CircuitBreakerConfig::new(0.5, Duration::from_secs(30)).with_window(4, 4);
+    let client = builder
+        .circuit_breaker("127.0.0.1", config)
+        .build()
+        .unwrap();
+    let url = server.url("/");
+
+    let error = get(&client, &server).await.unwrap_err();
+    assert!(
+        matches!(
+            error,
+            ReqwestBackoffError::CircuitOpen { retry_after: Some(wait), .. }
+                if wait == Duration::from_secs(30)
+        ),
+        "{error:?}"
+    );
+    assert_eq!(server.received_requests().len(), 4);
+    assert!(matches!(
+        client.circuit_state(&url),
+        Some(CircuitState::Open { .. })
+    ));
+
+    // Other calls fail fast without sending a request
+    let error = get(&client, &server).await.unwrap_err();
+    assert!(matches!(error, ReqwestBackoffError::CircuitOpen { .. }));
+    assert_eq!(server.received_requests().len(), 4);
+
+    clock.advance(Duration::from_secs(30));
+    server.push_responses([MockResponse::ok()]);
+    let response = get(&client, &server).await.unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(client.circuit_state(&url), Some(CircuitState::Closed));
+}
+
+#[tokio::test]
+async fn failed_trial_opens_the_breaker_again() {
+    let server = MockServer::start(vec![MockResponse::new(503); 3])
+        .await
+        .unwrap();
+    let (builder, clock) = mock_client(DefaultPolicy::default());
+    let config = CircuitBreakerConfig::new(1.0, Duration::from_secs(60)).with_window(2, 2);
+    let client = builder
+        .circuit_breaker("127.0.0.1", config)
+        .build()
+        .unwrap();
+
+    get(&client, &server).await.unwrap_err();
+    assert_eq!(server.received_requests().len(), 2);
+
+    clock.advance(Duration::from_secs(60));
+    let error = get(&client, &server).await.unwrap_err();
+    assert!(
+        matches!(
+            error,
+            ReqwestBackoffError::CircuitOpen { retry_after: Some(wait), .. }
+                if wait == Duration::from_secs(60)
+        ),
+        "{error:?}"
+    );
+    assert_eq!(server.received_requests().len(), 3);
+}
+
+#[tokio::test]
+async fn cancelled_trial_opens_the_breaker_again() {
+    let server = MockServer::start(vec![MockResponse::new(503); 2])
+        .await
+        .unwrap();
+    let (builder, clock) = mock_client(DefaultPolicy::default());
+    let config = CircuitBreakerConfig::new(1.0, Duration::from_secs(60)).with_window(2, 2);
+    let client = builder
+        .circuit_breaker("127.0.0.1", config)
+        .build()
+        .unwrap();
+    let url = server.url("/");
+
+    get(&client, &server).await.unwrap_err();
+    clock.advance(Duration::from_secs(60));
+    server.push_responses([MockResponse::ok().delay(Duration::from_secs(5))]);
+    let trial = tokio::time::timeout(Duration::from_millis(50), get(&client, &server)).await;
+    assert!(trial.is_err());
+    assert!(
+        matches!(client.circuit_state(&url), Some(CircuitState::Open { .. })),
+        "{:?}",
+        client.circuit_state(&url)
+    );
+
+    clock.advance(Duration::from_secs(60));
+    server.push_responses([MockResponse::ok()]);
+    let response = get(&client, &server).await.unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(client.circuit_state(&url), Some(CircuitState::Closed));
+}
+
+#[tokio::test]
+async fn local_errors_dont_count_as_failures() {
+    let server = MockServer::start(vec![MockResponse::new(503); 2])
+        .await
+        .unwrap();
+    let (builder, clock) = mock_client(DefaultPolicy::default());
+    let config = CircuitBreakerConfig::new(1.0, Duration::from_secs(60)).with_window(2, 2);
+    let client = builder
+        .circuit_breaker("127.0.0.1", config)
+        .build()
+        .unwrap();
+    let url = server.url("/");
+
+    get(&client, &server).await.unwrap_err();
+    clock.advance(Duration::from_secs(60));
+    let missing = std::env::temp_dir().join("twba-circuit-missing.bin");
+    let request = client.put(url.clone()).build().unwrap();
+    let error = client
+        .execute_with_body(request, BodySource::file(missing))
+        .await
+        .unwrap_err();
+    assert!(matches!(error, ReqwestBackoffError::Io(_)), "{error:?}");
+    assert_eq!(server.received_requests().len(), 2);
+
+    // The trial request is still available
+    server.push_responses([MockResponse::ok()]);
+    let response = get(&client, &server).await.unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(client.circuit_state(&url), Some(CircuitState::Closed));
+}
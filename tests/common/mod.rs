@@ -0,0 +1,26 @@
+#![allow(dead_code)]
+
+use chrono::DateTime;
+use twba_reqwest_backoff::clock::MockClock;
+use twba_reqwest_backoff::testing::MockServer;
+use twba_reqwest_backoff::{
+    BackoffPolicy, ReqwestBackoffError, ReqwestClient, ReqwestClientBuilder,
+};
+
+/// A builder using `policy` for the mock server and a [`MockClock`] for time.
+pub fn mock_client(policy: impl BackoffPolicy + 'static) -> (ReqwestClientBuilder, MockClock) {
+    let clock = MockClock::new(DateTime::from_timestamp(1_700_000_000, 0).unwrap());
+    let builder = ReqwestClient::builder()
+        .policy("127.0.0.1", policy)
+        .clock(clock.clone())
+        .sleeper(clock.clone());
+    (builder, clock)
+}
+
+pub async fn get(
+    client: &ReqwestClient,
+    server: &MockServer,
+) -> Result<reqwest::Response, ReqwestBackoffError> {
+    let request = client.get(server.url("/")).build().unwrap();
+    client.execute_with_backoff(request).await
+}
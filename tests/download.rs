@@ -0,0 +1,284 @@
+mod common;
+
+use std::time::Duration;
+
+use common::mock_client;
+use twba_reqwest_backoff::testing::{MockResponse, MockServer};
+use twba_reqwest_backoff::{BackoffConfig, DefaultPolicy, RequestOverrides, ReqwestBackoffError};
+
+const BODY: &str = "0123456789abcdefghij";
+
+#[tokio::test]
+async fn download_resumes_with_range_after_dropped_connection() {
+    let server = MockServer::start([
+        MockResponse::ok()
+            .header("ETag", "\"v1\"")
+            .body(BODY)
+            .truncate_body(8),
+        MockResponse::new(206)
+            .header("ETag", "\"v1\"")
+            .header("Content-Range", "bytes 8-19/20")
+            .body(&BODY[8..]),
+    ])
+    .await
+    .unwrap();
+    let (builder, clock) = mock_client(DefaultPolicy::default());
+    let client = builder.build().unwrap();
+
+    let mut written = Vec::new();
+    let request = client.get(server.url("/vod.ts")).build().unwrap();
+    let len = client
+        .download_with_backoff(request, &mut written)
+        .await
+        .unwrap();
+
+    assert_eq!(len, 20);
+    assert_eq!(written, BODY.as_bytes());
+    assert_eq!(clock.sleeps().len(), 1);
+    let requests = server.received_requests();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[1].headers["range"], "bytes=8-");
+    assert_eq!(requests[1].headers["if-range"], "\"v1\"");
+}
+
+#[tokio::test]
+async fn download_detects_changed_resource() {
+    let server = MockServer::start([
+        MockResponse::ok()
+            .header("ETag", "\"v1\"")
+            .body(BODY)
+            .truncate_body(8),
+        MockResponse::ok()
+            .header("ETag", "\"v2\"")
+            .body("new content"),
+    ])
+    .await
+    .unwrap();
+    let (builder, _clock) = mock_client(DefaultPolicy::default());
+    let client = builder.build().unwrap();
+
+    let mut written = Vec::new();
+    let request = client.get(server.url("/vod.ts")).build().unwrap();
+    let error = client
+        .download_with_backoff(request, &mut written)
+        .await
+        .unwrap_err();
+
+    assert!(
+        matches!(
+            error,
+            ReqwestBackoffError::ResourceChanged { received: 8, .. }
+        ),
+        "{error:?}"
+    );
+    assert_eq!(written, &BODY.as_bytes()[..8]);
+}
+
+#[tokio::test]
+async fn download_fails_if_the_server_ignores_the_range() {
+    let server = MockServer::start([
+        MockResponse::ok().body(BODY).truncate_body(8),
+        MockResponse::ok().body(BODY),
+    ])
+    .await
+    .unwrap();
+    let (builder, _clock) = mock_client(DefaultPolicy::default());
+    let client = builder.build().unwrap();
+
+    let mut written = Vec::new();
+    let request = client.get(server.url("/vod.ts")).build().unwrap();
+    let error = client
+        .download_with_backoff(request, &mut written)
+        .await
+        .unwrap_err();
+
+    assert!(
+        matches!(
+            error,
+            ReqwestBackoffError::RangeNotSupported { received: 8, .. }
+        ),
+        "{error:?}"
+    );
+}
+
+#[tokio::test]
+async fn resumed_requests_share_the_attempts() {
+    let server = MockServer::start([
+        MockResponse::new(503),
+        MockResponse::ok().body(BODY).truncate_body(4),
+        MockResponse::new(503),
+        MockResponse::ok(),
+    ])
+    .await
+    .unwrap();
+    let config = BackoffConfig::default().with_max_attempts(3);
+    let (builder, clock) = mock_client(DefaultPolicy::new(config));
+    let client = builder.build().unwrap();
+
+    let mut written = Vec::new();
+    let request = client.get(server.url("/vod.ts")).build().unwrap();
+    let error = client
+        .download_with_backoff(request, &mut written)
+        .await
+        .unwrap_err();
+
+    assert!(
+        matches!(
+            error,
+            ReqwestBackoffError::BackoffExceeded {
+                backoff_attempts: 3,
+                ..
+            }
+        ),
+        "{error:?}"
+    );
+    assert_eq!(server.received_requests().len(), 3);
+    assert_eq!(clock.sleeps().len(), 2);
+}
+
+#[tokio::test]
+async fn resumed_requests_share_the_deadline() {
+    let server = MockServer::start([
+        MockResponse::ok().body(BODY).truncate_body(4),
+        MockResponse::new(503),
+        MockResponse::new(503),
+    ])
+    .await
+    .unwrap();
+    let delay = Duration::from_secs(4);
+    let config = BackoffConfig::default()
+        .with_base_delay(delay)
+        .with_max_delay(delay);
+    let (builder, clock) = mock_client(DefaultPolicy::new(config));
+    let client = builder
+        .max_elapsed(Duration::from_secs(10))
+        .build()
+        .unwrap();
+
+    let mut written = Vec::new();
+    let request = client.get(server.url("/vod.ts")).build().unwrap();
+    let error = client
+        .download_with_backoff(request, &mut written)
+        .await
+        .unwrap_err();
+
+    match error {
+        ReqwestBackoffError::DeadlineExceeded {
+            elapsed, attempts, ..
+        } => {
+            assert_eq!(elapsed, Duration::from_secs(8));
+            assert_eq!(attempts, 3);
+        }
+        error => panic!("unexpected error: {:?}", error),
+    }
+    assert_eq!(clock.total_slept(), Duration::from_secs(8));
+}
+
+#[tokio::test]
+async fn max_attempts_override_limits_interruptions() {
+    let server = MockServer::start([
+        MockResponse::ok()
+            .header("ETag", "\"v1\"")
+            .body(BODY)
+            .truncate_body(4),
+        MockResponse::new(206)
+            .header("ETag", "\"v1\"")
+            .header("Content-Range", "bytes 4-19/20")
+            .body(&BODY[4..])
+            .truncate_body(4),
+        MockResponse::new(206)
+            .header("ETag", "\"v1\"")
+            .header("Content-Range", "bytes 8-19/20")
+            .body(&BODY[8..]),
+    ])
+    .await
+    .unwrap();
+    let (builder, clock) = mock_client(DefaultPolicy::default());
+    let client = builder.build().unwrap();
+
+    let mut written = Vec::new();
+    let request = client.get(server.url("/vod.ts")).build().unwrap();
+    let request = RequestOverrides::new()
+        .with_max_attempts(2)
+        .attach_to(request)
+        .unwrap();
+    let error = client
+        .download_with_backoff(request, &mut written)
+        .await
+        .unwrap_err();
+
+    assert!(
+        matches!(
+            error,
+            ReqwestBackoffError::BackoffExceeded {
+                backoff_attempts: 2,
+                ..
+            }
+        ),
+        "{error:?}"
+    );
+    assert_eq!(written, &BODY.as_bytes()[..8]);
+    assert_eq!(server.received_requests().len(), 2);
+    assert_eq!(clock.sleeps().len(), 1);
+}
+
+#[tokio::test]
+async fn download_resumes_within_the_requested_range() {
+    let server = MockServer::start([
+        MockResponse::new(206)
+            .header("ETag", "\"v1\"")
+            .header("Content-Range", "bytes 4-15/20")
+            .body(&BODY[4..16])
+            .truncate_body(5),
+        MockResponse::new(206)
+            .header("ETag", "\"v1\"")
+            .header("Content-Range", "bytes 9-15/20")
+            .body(&BODY[9..16]),
+    ])
+    .await
+    .unwrap();
+    let (builder, _clock) = mock_client(DefaultPolicy::default());
+    let client = builder.build().unwrap();
+
+    let mut written = Vec::new();
+    let request = client
+        .get(server.url("/vod.ts"))
+        .header("Range", "bytes=4-15")
+        .build()
+        .unwrap();
+    let len = client
+        .download_with_backoff(request, &mut written)
+        .await
+        .unwrap();
+
+    assert_eq!(len, 12);
+    assert_eq!(written, &BODY.as_bytes()[4..16]);
+    let requests = server.received_requests();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[0].headers["range"], "bytes=4-15");
+    assert_eq!(requests[1].headers["range"], "bytes=9-15");
+}
+
+#[tokio::test]
+async fn suffix_ranges_are_rejected() {
+    let server = MockServer::start([]).await.unwrap();
+    let (builder, _clock) = mock_client(DefaultPolicy::default());
+    let client = builder.build().unwrap();
+
+    let mut written = Vec::new();
+    let request = client
+        .get(server.url("/vod.ts"))
+        .header("Range", "bytes=-500")
+        .build()
+        .unwrap();
+    let error = client
+        .download_with_backoff(request, &mut written)
+        .await
+        .unwrap_err();
+
+    assert!(
+        matches!(&error, ReqwestBackoffError::InvalidHeader { name, .. } if name == "range"),
+        "{error:?}"
+    );
+    assert!(server.received_requests().is_empty());
+}
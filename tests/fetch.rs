@@ -0,0 +1,114 @@
+mod common;
+
+use std::time::Duration;
+
+use common::mock_client;
+use serde_json::json;
+use twba_reqwest_backoff::testing::{MockResponse, MockServer};
+use twba_reqwest_backoff::{BackoffConfig, DefaultPolicy, RequestOverrides, ReqwestBackoffError};
+
+#[tokio::test]
+async fn json_is_fetched_again_after_body_is_cut_off() {
+    let body = json!({ "data": [{ "id": "1" }, { "id": "2" }] });
+    let server = MockServer::start([
+        MockResponse::ok().json(&body).truncate_body(10),
+        MockResponse::ok().json(&body),
+    ])
+    .await
+    .unwrap();
+    let (builder, clock) = mock_client(DefaultPolicy::default());
+    let client = builder.build().unwrap();
+
+    let request = client.get(server.url("/videos")).build().unwrap();
+    let value: serde_json::Value = client.get_json_with_backoff(request).await.unwrap();
+
+    assert_eq!(value, body);
+    assert_eq!(server.received_requests().len(), 2);
+    assert_eq!(clock.sleeps().len(), 1);
+}
+
+#[tokio::test]
+async fn invalid_json_is_a_decode_error_without_retry() {
+    let server = MockServer::start([MockResponse::ok().body("not json")])
+        .await
+        .unwrap();
+    let (builder, clock) = mock_client(DefaultPolicy::default());
+    let client = builder.build().unwrap();
+
+    let request = client.get(server.url("/videos")).build().unwrap();
+    let error = client
+        .get_json_with_backoff::<serde_json::Value>(request)
+        .await
+        .unwrap_err();
+
+    assert!(
+        matches!(error, ReqwestBackoffError::Decode { status, .. } if status == 200),
+        "{error:?}"
+    );
+    assert_eq!(server.received_requests().len(), 1);
+    assert!(clock.sleeps().is_empty());
+}
+
+#[tokio::test]
+async fn no_retry_returns_the_interrupted_body_as_is() {
+    let server = MockServer::start([
+        MockResponse::ok().body("{\"id\": \"1\"}").truncate_body(4),
+        MockResponse::ok().body("{\"id\": \"1\"}"),
+    ])
+    .await
+    .unwrap();
+    let (builder, clock) = mock_client(DefaultPolicy::default());
+    let client = builder.build().unwrap();
+
+    let request = client.get(server.url("/videos")).build().unwrap();
+    let request = RequestOverrides::new()
+        .no_retry()
+        .attach_to(request)
+        .unwrap();
+    let error = client
+        .get_json_with_backoff::<serde_json::Value>(request)
+        .await
+        .unwrap_err();
+
+    assert!(
+        matches!(error, ReqwestBackoffError::Reqwest(_)),
+        "{error:?}"
+    );
+    assert_eq!(server.received_requests().len(), 1);
+    assert!(clock.sleeps().is_empty());
+}
+
+#[tokio::test]
+async fn deadline_override_limits_interruptions() {
+    let server = MockServer::start(vec![
+        MockResponse::ok().body("0123456789").truncate_body(4);
+        5
+    ])
+    .await
+    .unwrap();
+    let delay = Duration::from_secs(4);
+    let config = BackoffConfig::default()
+        .with_base_delay(delay)
+        .with_max_delay(delay);
+    let (builder, clock) = mock_client(DefaultPolicy::new(config));
+    let client = builder.build().unwrap();
+
+    let request = client.get(server.url("/videos")).build().unwrap();
+    let request = RequestOverrides::new()
+        .with_deadline(Duration::from_secs(6))
+        .attach_to(request)
+        .unwrap();
+    let error = client.get_bytes_with_backoff(request).await.unwrap_err();
+
+    match error {
+        ReqwestBackoffError::DeadlineExceeded {
+            elapsed, attempts, ..
+        } => {
+            assert_eq!(elapsed, delay);
+            assert_eq!(attempts, 2);
+        }
+        error => panic!("unexpected error: {:?}", error),
+    }
+    assert_eq!(server.received_requests().len(), 2);
+    assert_eq!(clock.total_slept(), delay);
+}
@@ -0,0 +1,88 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use common::{get, mock_client};
+use reqwest::StatusCode;
+use twba_reqwest_backoff::testing::{MockResponse, MockServer};
+use twba_reqwest_backoff::{
+    AttemptOutcome, BackoffConfig, DefaultPolicy, ReqwestBackoffError, RetryAction, RetryEvent,
+};
+
+fn recorder() -> (Arc<Mutex<Vec<RetryEvent>>>, impl Fn(RetryEvent) + Clone) {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let record = {
+        let events = events.clone();
+        move |event| events.lock().unwrap().push(event)
+    };
+    (events, record)
+}
+
+#[tokio::test]
+async fn hooks_see_retries_and_success() {
+    let server = MockServer::start([MockResponse::new(503), MockResponse::ok()])
+        .await
+        .unwrap();
+    let (retries, record_retry) = recorder();
+    let (successes, record_success) = recorder();
+    let (builder, _clock) = mock_client(DefaultPolicy::default());
+    let client = builder
+        .on_retry(move |event| {
+            record_retry(event);
+            async { RetryAction::Continue }
+        })
+        .on_success(move |event| {
+            record_success(event);
+            async {}
+        })
+        .build()
+        .unwrap();
+
+    get(&client, &server).await.unwrap();
+
+    let retries = retries.lock().unwrap();
+    assert_eq!(retries.len(), 1);
+    assert_eq!(retries[0].attempt, 1);
+    assert_eq!(
+        retries[0].outcome,
+        AttemptOutcome::Status(StatusCode::SERVICE_UNAVAILABLE)
+    );
+    assert_eq!(retries[0].delay, Some(BackoffConfig::default().base_delay));
+    let successes = successes.lock().unwrap();
+    assert_eq!(successes.len(), 1);
+    assert_eq!(successes[0].attempt, 2);
+    assert_eq!(successes[0].outcome, AttemptOutcome::Status(StatusCode::OK));
+}
+
+#[tokio::test]
+async fn on_retry_can_abort() {
+    let server = MockServer::start([MockResponse::new(503), MockResponse::ok()])
+        .await
+        .unwrap();
+    let (give_ups, record_give_up) = recorder();
+    let (builder, clock) = mock_client(DefaultPolicy::default());
+    let client = builder
+        .on_retry(|_| async { RetryAction::Abort })
+        .on_give_up(move |event| {
+            record_give_up(event);
+            async {}
+        })
+        .build()
+        .unwrap();
+
+    let error = get(&client, &server).await.unwrap_err();
+
+    assert!(matches!(
+        error,
+        ReqwestBackoffError::Aborted { attempts: 1, .. }
+    ));
+    assert_eq!(server.received_requests().len(), 1);
+    assert_eq!(clock.total_slept(), Duration::ZERO);
+    let give_ups = give_ups.lock().unwrap();
+    assert_eq!(give_ups.len(), 1);
+    assert_eq!(
+        give_ups[0].outcome,
+        AttemptOutcome::Status(StatusCode::SERVICE_UNAVAILABLE)
+    );
+}
@@ -0,0 +1,129 @@
+mod common;
+
+use common::mock_client;
+use twba_reqwest_backoff::testing::{scenarios, MockResponse, MockServer};
+use twba_reqwest_backoff::{DefaultPolicy, GooglePolicy, ReqwestBackoffError, UnsafeRetries};
+
+#[tokio::test]
+async fn post_is_not_retried_after_the_server_may_have_acted() {
+    let server = MockServer::start([MockResponse::new(503), MockResponse::ok()])
+        .await
+        .unwrap();
+    let (builder, clock) = mock_client(DefaultPolicy::default());
+    let client = builder.build().unwrap();
+
+    let request = client.post(server.url("/clips")).build().unwrap();
+    let response = client.execute_with_backoff(request).await.unwrap();
+
+    assert_eq!(response.status(), 503);
+    assert_eq!(server.received_requests().len(), 1);
+    assert!(clock.sleeps().is_empty());
+}
+
+#[tokio::test]
+async fn post_is_retried_after_429() {
+    let server = MockServer::start([MockResponse::new(429), MockResponse::ok()])
+        .await
+        .unwrap();
+    let (builder, _clock) = mock_client(DefaultPolicy::default());
+    let client = builder.build().unwrap();
+
+    let request = client.post(server.url("/clips")).build().unwrap();
+    let response = client.execute_with_backoff(request).await.unwrap();
+
+    assert_eq!(response.status(), 200);
+    assert_eq!(server.received_requests().len(), 2);
+}
+
+#[tokio::test]
+async fn post_is_only_retried_after_google_rate_limit_reasons() {
+    let server = MockServer::start([
+        scenarios::google_error_response(403, "rateLimitExceeded"),
+        scenarios::google_error_response(403, "backendError"),
+        MockResponse::ok(),
+    ])
+    .await
+    .unwrap();
+    let (builder, clock) = mock_client(GooglePolicy::default());
+    let client = builder.build().unwrap();
+
+    let request = client.post(server.url("/videos")).build().unwrap();
+    let response = client.execute_with_backoff(request).await.unwrap();
+
+    assert_eq!(response.status(), 403);
+    assert_eq!(server.received_requests().len(), 2);
+    assert_eq!(clock.sleeps().len(), 1);
+}
+
+#[tokio::test]
+async fn generated_idempotency_key_is_kept_across_attempts() {
+    let server = MockServer::start([MockResponse::new(503), MockResponse::ok()])
+        .await
+        .unwrap();
+    let (builder, _clock) = mock_client(DefaultPolicy::default());
+    let client = builder
+        .unsafe_retries(UnsafeRetries::IdempotencyKey)
+        .build()
+        .unwrap();
+
+    let request = client.post(server.url("/clips")).build().unwrap();
+    let response = client.execute_with_backoff(request).await.unwrap();
+
+    assert_eq!(response.status(), 200);
+    let requests = server.received_requests();
+    assert_eq!(requests.len(), 2);
+    let key = &requests[0].headers["idempotency-key"];
+    assert_eq!(key.len(), 36);
+    assert_eq!(&requests[1].headers["idempotency-key"], key);
+}
+
+#[tokio::test]
+async fn post_is_not_fetched_again_after_body_is_cut_off() {
+    let server = MockServer::start([
+        MockResponse::ok().body("{\"id\": \"1\"}").truncate_body(4),
+        MockResponse::ok().body("{\"id\": \"1\"}"),
+    ])
+    .await
+    .unwrap();
+    let (builder, clock) = mock_client(DefaultPolicy::default());
+    let client = builder.build().unwrap();
+
+    let request = client.post(server.url("/clips")).build().unwrap();
+    let error = client
+        .get_json_with_backoff::<serde_json::Value>(request)
+        .await
+        .unwrap_err();
+
+    assert!(
+        matches!(error, ReqwestBackoffError::Reqwest(_)),
+        "{error:?}"
+    );
+    assert_eq!(server.received_requests().len(), 1);
+    assert!(clock.sleeps().is_empty());
+}
+
+#[tokio::test]
+async fn post_with_idempotency_key_is_fetched_again_after_body_is_cut_off() {
+    let server = MockServer::start([
+        MockResponse::ok().body("{\"id\": \"1\"}").truncate_body(4),
+        MockResponse::ok().body("{\"id\": \"1\"}"),
+    ])
+    .await
+    .unwrap();
+    let (builder, _clock) = mock_client(DefaultPolicy::default());
+    let client = builder
+        .unsafe_retries(UnsafeRetries::IdempotencyKey)
+        .build()
+        .unwrap();
+
+    let request = client.post(server.url("/clips")).build().unwrap();
+    let value: serde_json::Value = client.get_json_with_backoff(request).await.unwrap();
+
+    assert_eq!(value["id"], "1");
+    let requests = server.received_requests();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(
+        requests[0].headers["idempotency-key"],
+        requests[1].headers["idempotency-key"]
+    );
+}
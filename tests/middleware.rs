@@ -0,0 +1,43 @@
+mod common;
+
+use common::mock_client;
+use twba_reqwest_backoff::middleware::BackoffMiddleware;
+use twba_reqwest_backoff::testing::{scenarios, MockServer};
+use twba_reqwest_backoff::{DefaultPolicy, RequestOverrides};
+
+#[tokio::test]
+async fn middleware_retries_requests_of_the_client() {
+    let server = MockServer::start(scenarios::retry_after(3)).await.unwrap();
+    let (builder, clock) = mock_client(DefaultPolicy::default());
+    let backoff = builder.build().unwrap();
+    let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
+        .with(BackoffMiddleware::new(backoff))
+        .build();
+
+    let response = client.get(server.url("/")).send().await.unwrap();
+
+    assert_eq!(response.status(), 200);
+    assert_eq!(server.received_requests().len(), 3);
+    assert_eq!(clock.total_slept().as_secs(), 6);
+}
+
+#[tokio::test]
+async fn overrides_in_extensions_are_applied() {
+    let server = MockServer::start(scenarios::retry_after(3)).await.unwrap();
+    let (builder, clock) = mock_client(DefaultPolicy::default());
+    let backoff = builder.build().unwrap();
+    let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
+        .with(BackoffMiddleware::new(backoff))
+        .build();
+
+    let response = client
+        .get(server.url("/health"))
+        .with_extension(RequestOverrides::new().no_retry())
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), 429);
+    assert_eq!(server.received_requests().len(), 1);
+    assert!(clock.sleeps().is_empty());
+}
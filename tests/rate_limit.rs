@@ -0,0 +1,127 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use common::{get, mock_client};
+use twba_reqwest_backoff::clock::{BoxFuture, Clock, Sleeper};
+use twba_reqwest_backoff::testing::{MockResponse, MockServer};
+use twba_reqwest_backoff::{DefaultPolicy, RateLimit};
+
+/// Records sleeps without moving the clock, as if all requests were sent at the same time.
+#[derive(Debug, Clone, Default)]
+struct FrozenSleeper(Arc<Mutex<Vec<Duration>>>);
+
+impl Sleeper for FrozenSleeper {
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        self.0.lock().unwrap().push(duration);
+        Box::pin(std::future::ready(()))
+    }
+}
+
+fn millis(millis: &[u64]) -> Vec<Duration> {
+    millis.iter().copied().map(Duration::from_millis).collect()
+}
+
+#[tokio::test]
+async fn burst_is_sent_at_once_then_requests_wait_for_refill() {
+    let server = MockServer::start(vec![MockResponse::ok(); 4])
+        .await
+        .unwrap();
+    let (builder, clock) = mock_client(DefaultPolicy::default());
+    let client = builder
+        .rate_limit("127.0.0.1", RateLimit::new(2.0, 3))
+        .build()
+        .unwrap();
+
+    for _ in 0..4 {
+        get(&client, &server).await.unwrap();
+    }
+
+    assert_eq!(clock.sleeps(), millis(&[500]));
+}
+
+#[tokio::test]
+async fn tokens_refill_while_idle_up_to_the_burst() {
+    let server = MockServer::start(vec![MockResponse::ok(); 6])
+        .await
+        .unwrap();
+    let (builder, clock) = mock_client(DefaultPolicy::default());
+    let client = builder
+        .rate_limit("127.0.0.1", RateLimit::new(2.0, 2))
+        .build()
+        .unwrap();
+
+    get(&client, &server).await.unwrap();
+    get(&client, &server).await.unwrap();
+    clock.advance(Duration::from_secs(60));
+    for _ in 0..3 {
+        get(&client, &server).await.unwrap();
+    }
+
+    assert_eq!(clock.sleeps(), millis(&[500]));
+}
+
+#[tokio::test]
+async fn waiting_requests_make_later_requests_wait_longer() {
+    let server = MockServer::start(vec![MockResponse::ok(); 5])
+        .await
+        .unwrap();
+    let (builder, _clock) = mock_client(DefaultPolicy::default());
+    let sleeper = FrozenSleeper::default();
+    let client = builder
+        .sleeper(sleeper.clone())
+        .rate_limit("127.0.0.1", RateLimit::new(2.0, 2))
+        .build()
+        .unwrap();
+
+    for _ in 0..5 {
+        get(&client, &server).await.unwrap();
+    }
+
+    assert_eq!(*sleeper.0.lock().unwrap(), millis(&[500, 1000, 1500]));
+}
+
+#[tokio::test]
+async fn exhausted_server_rate_limit_blocks_until_the_reset() {
+    let (builder, clock) = mock_client(DefaultPolicy::default());
+    let reset = clock.now() + Duration::from_secs(10);
+    let server = MockServer::start([
+        MockResponse::ok()
+            .header("Ratelimit-Limit", 100)
+            .header("Ratelimit-Remaining", 0)
+            .header("Ratelimit-Reset", reset.timestamp()),
+        MockResponse::ok(),
+        MockResponse::ok(),
+    ])
+    .await
+    .unwrap();
+    let client = builder
+        .rate_limit("127.0.0.1", RateLimit::new(10.0, 10))
+        .build()
+        .unwrap();
+
+    for _ in 0..3 {
+        get(&client, &server).await.unwrap();
+    }
+
+    assert_eq!(clock.sleeps(), vec![Duration::from_secs(10)]);
+}
+
+#[tokio::test]
+async fn clones_share_the_bucket() {
+    let server = MockServer::start(vec![MockResponse::ok(); 2])
+        .await
+        .unwrap();
+    let (builder, clock) = mock_client(DefaultPolicy::default());
+    let client = builder
+        .rate_limit("127.0.0.1", RateLimit::new(1.0, 1))
+        .build()
+        .unwrap();
+    let clone = client.clone();
+
+    get(&client, &server).await.unwrap();
+    get(&clone, &server).await.unwrap();
+
+    assert_eq!(clock.sleeps(), vec![Duration::from_secs(1)]);
+}
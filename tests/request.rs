@@ -0,0 +1,175 @@
+mod common;
+
+use std::time::Duration;
+
+use common::mock_client;
+use reqwest::StatusCode;
+use twba_reqwest_backoff::testing::{MockResponse, MockServer};
+use twba_reqwest_backoff::{
+    BackoffConfig, DefaultPolicy, RequestBuilderExt, RequestOverrides, ReqwestBackoffError,
+};
+
+#[tokio::test]
+async fn max_attempts_overrides_the_host_policy() {
+    let server = MockServer::start(vec![MockResponse::new(503); 5])
+        .await
+        .unwrap();
+    let (builder, _clock) = mock_client(DefaultPolicy::default());
+    let client = builder.build().unwrap();
+
+    let error = client
+        .get(server.url("/"))
+        .query(&[("page", "2")])
+        .max_attempts(2)
+        .send_with_backoff(&client)
+        .await
+        .unwrap_err();
+
+    assert!(
+        matches!(
+            error,
+            ReqwestBackoffError::BackoffExceeded {
+                backoff_attempts: 2,
+                ..
+            }
+        ),
+        "{error:?}"
+    );
+    let requests = server.received_requests();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[0].path, "/?page=2");
+}
+
+#[tokio::test]
+async fn max_attempts_of_one_sends_once() {
+    let server = MockServer::start([MockResponse::new(503), MockResponse::ok()])
+        .await
+        .unwrap();
+    let (builder, clock) = mock_client(DefaultPolicy::default());
+    let client = builder.build().unwrap();
+
+    let error = client
+        .get(server.url("/"))
+        .max_attempts(1)
+        .send_with_backoff(&client)
+        .await
+        .unwrap_err();
+
+    assert!(
+        matches!(
+            error,
+            ReqwestBackoffError::BackoffExceeded {
+                backoff_attempts: 1,
+                ..
+            }
+        ),
+        "{error:?}"
+    );
+    assert_eq!(server.received_requests().len(), 1);
+    assert!(clock.sleeps().is_empty());
+}
+
+#[tokio::test]
+async fn no_retry_returns_the_response_as_is() {
+    let server = MockServer::start([MockResponse::new(503), MockResponse::ok()])
+        .await
+        .unwrap();
+    let (builder, clock) = mock_client(DefaultPolicy::default());
+    let client = builder.build().unwrap();
+
+    let response = client
+        .get(server.url("/health"))
+        .no_retry()
+        .send_with_backoff(&client)
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), 503);
+    assert_eq!(server.received_requests().len(), 1);
+    assert!(clock.sleeps().is_empty());
+}
+
+#[tokio::test]
+async fn policy_replaces_the_host_policy() {
+    let server = MockServer::start([MockResponse::new(503), MockResponse::ok()])
+        .await
+        .unwrap();
+    let (builder, clock) = mock_client(DefaultPolicy::default());
+    let client = builder.build().unwrap();
+    let config = BackoffConfig::default()
+        .with_base_delay(Duration::from_secs(1))
+        .with_max_delay(Duration::from_secs(1));
+
+    let response = client
+        .get(server.url("/"))
+        .policy(DefaultPolicy::new(config))
+        .send_with_backoff(&client)
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), 200);
+    assert_eq!(clock.sleeps(), [Duration::from_secs(1)]);
+}
+
+#[tokio::test]
+async fn builder_errors_are_reported_separately() {
+    let client = twba_reqwest_backoff::ReqwestClient::new();
+
+    let error = client
+        .get("not a url")
+        .send_with_backoff(&client)
+        .await
+        .unwrap_err();
+
+    assert!(matches!(error, ReqwestBackoffError::Build(_)), "{error:?}");
+}
+
+#[tokio::test]
+async fn overrides_are_read_from_the_request_extensions() {
+    let server = MockServer::start([
+        MockResponse::new(500),
+        MockResponse::new(500),
+        MockResponse::ok(),
+    ])
+    .await
+    .unwrap();
+    let (builder, _clock) = mock_client(DefaultPolicy::default());
+    let client = builder.build().unwrap();
+
+    let request = client.get(server.url("/batch")).build().unwrap();
+    let request = RequestOverrides::new()
+        .with_retryable_statuses([StatusCode::INTERNAL_SERVER_ERROR])
+        .attach_to(request)
+        .unwrap();
+    let response = client.execute_with_backoff(request).await.unwrap();
+
+    assert_eq!(response.status(), 200);
+    let requests = server.received_requests();
+    assert_eq!(requests.len(), 3);
+    assert!(requests[0].headers.get("content-length").is_none());
+}
+
+#[tokio::test]
+async fn deadline_override_replaces_the_client_deadline() {
+    let server = MockServer::start(vec![MockResponse::new(503); 10])
+        .await
+        .unwrap();
+    let (builder, _clock) = mock_client(DefaultPolicy::default());
+    let client = builder.build().unwrap();
+
+    let request = client.get(server.url("/probe")).build().unwrap();
+    let request = RequestOverrides::new()
+        .with_deadline(Duration::from_secs(12))
+        .attach_to(request)
+        .unwrap();
+    let error = client.execute_with_backoff(request).await.unwrap_err();
+
+    assert!(
+        matches!(
+            error,
+            ReqwestBackoffError::DeadlineExceeded { attempts: 3, .. }
+        ),
+        "{error:?}"
+    );
+    assert_eq!(server.received_requests().len(), 3);
+}
@@ -0,0 +1,99 @@
+mod common;
+
+use std::future::{poll_fn, ready, Ready};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use common::mock_client;
+use tower_layer::Layer;
+use tower_service::Service;
+use twba_reqwest_backoff::testing::{scenarios, MockResponse, MockServer};
+use twba_reqwest_backoff::tower::BackoffLayer;
+use twba_reqwest_backoff::{DefaultPolicy, RequestOverrides, ReqwestBackoffError};
+
+#[tokio::test]
+async fn layer_retries_requests_of_the_inner_service() {
+    let server = MockServer::start(scenarios::retry_after(3)).await.unwrap();
+    let (builder, clock) = mock_client(DefaultPolicy::default());
+    let client = builder.build().unwrap();
+    let mut service = BackoffLayer::new(client).layer(reqwest::Client::new());
+
+    poll_fn(|cx| service.poll_ready(cx)).await.unwrap();
+    let request = reqwest::Request::new(reqwest::Method::GET, server.url("/"));
+    let response = service.call(request).await.unwrap();
+
+    assert_eq!(response.status(), 200);
+    assert_eq!(server.received_requests().len(), 3);
+    assert_eq!(clock.total_slept().as_secs(), 6);
+}
+
+#[tokio::test]
+async fn layer_applies_overrides_in_the_extensions() {
+    let server = MockServer::start(vec![MockResponse::new(503); 3])
+        .await
+        .unwrap();
+    let (builder, _clock) = mock_client(DefaultPolicy::default());
+    let client = builder.build().unwrap();
+    let mut service = BackoffLayer::new(client).layer(reqwest::Client::new());
+
+    let request = reqwest::Request::new(reqwest::Method::GET, server.url("/"));
+    let request = RequestOverrides::new()
+        .with_max_attempts(2)
+        .attach_to(request)
+        .unwrap();
+    poll_fn(|cx| service.poll_ready(cx)).await.unwrap();
+    let error = service.call(request).await.unwrap_err();
+
+    assert!(
+        matches!(
+            error,
+            ReqwestBackoffError::BackoffExceeded {
+                backoff_attempts: 2,
+                ..
+            }
+        ),
+        "{error:?}"
+    );
+    assert_eq!(server.received_requests().len(), 2);
+}
+
+/// Fails every request with an error that is not a [`reqwest::Error`], like tower's timeout layer.
+#[derive(Debug, Clone, Default)]
+struct TimingOut {
+    calls: Arc<AtomicUsize>,
+}
+
+impl Service<reqwest::Request> for TimingOut {
+    type Response = reqwest::Response;
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+    type Future = Ready<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _request: reqwest::Request) -> Self::Future {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        ready(Err("request timed out".into()))
+    }
+}
+
+#[tokio::test]
+async fn errors_other_than_reqwest_errors_are_not_retried() {
+    let (builder, clock) = mock_client(DefaultPolicy::default());
+    let client = builder.build().unwrap();
+    let inner = TimingOut::default();
+    let mut service = BackoffLayer::new(client).layer(inner.clone());
+
+    let request = reqwest::Request::new(reqwest::Method::GET, "http://127.0.0.1/".parse().unwrap());
+    poll_fn(|cx| service.poll_ready(cx)).await.unwrap();
+    let error = service.call(request).await.unwrap_err();
+
+    assert!(
+        matches!(&error, ReqwestBackoffError::Other(error) if error.to_string() == "request timed out"),
+        "{error:?}"
+    );
+    assert_eq!(inner.calls.load(Ordering::SeqCst), 1);
+    assert!(clock.sleeps().is_empty());
+}
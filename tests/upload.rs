@@ -0,0 +1,168 @@
+mod common;
+
+use common::mock_client;
+use twba_reqwest_backoff::testing::{MockResponse, MockServer};
+use twba_reqwest_backoff::upload::CHUNK_GRANULARITY;
+use twba_reqwest_backoff::{BackoffConfig, DefaultPolicy, ReqwestBackoffError, ResumableUpload};
+
+#[tokio::test]
+async fn upload_resumes_from_committed_offset_after_server_error() {
+    let data: Vec<u8> = (0..600 * 1024).map(|i| (i % 251) as u8).collect();
+    let path = temp_file("upload", &data);
+    let server = MockServer::start([]).await.unwrap();
+    let half = 3 * CHUNK_GRANULARITY / 2;
+    server.push_responses([
+        MockResponse::ok().header("Location", server.url("/session")),
+        MockResponse::new(308),
+        MockResponse::new(308).header("Range", format!("bytes=0-{}", CHUNK_GRANULARITY - 1)),
+        MockResponse::new(503),
+        MockResponse::new(308).header("Range", format!("bytes=0-{}", half - 1)),
+        MockResponse::new(201).body("done"),
+    ]);
+    let (builder, clock) = mock_client(DefaultPolicy::default());
+    let client = builder.build().unwrap();
+
+    let request = client.post(server.url("/upload")).build().unwrap();
+    let upload = client
+        .start_resumable_upload(request, "video/mp4", data.len() as u64)
+        .await
+        .unwrap()
+        .with_chunk_size(CHUNK_GRANULARITY);
+    let response = upload.upload_file(&client, &path).await.unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(response.status(), 201);
+    assert_eq!(clock.sleeps().len(), 1);
+    let requests = server.received_requests();
+    assert_eq!(requests.len(), 6);
+    assert_eq!(requests[0].headers["x-upload-content-type"], "video/mp4");
+    assert_eq!(requests[0].headers["x-upload-content-length"], "614400");
+    assert_eq!(requests[1].path, "/session");
+    assert_eq!(requests[1].headers["content-range"], "bytes */614400");
+    assert_eq!(
+        requests[2].headers["content-range"],
+        "bytes 0-262143/614400"
+    );
+    assert_eq!(requests[2].body.as_ref(), &data[..262144]);
+    assert_eq!(
+        requests[3].headers["content-range"],
+        "bytes 262144-524287/614400"
+    );
+    assert_eq!(requests[4].headers["content-range"], "bytes */614400");
+    assert_eq!(
+        requests[5].headers["content-range"],
+        "bytes 393216-614399/614400"
+    );
+    assert_eq!(requests[5].body.as_ref(), &data[393216..]);
+}
+
+fn temp_file(name: &str, data: &[u8]) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("twba-{}-{}.bin", name, std::process::id()));
+    std::fs::write(&path, data).unwrap();
+    path
+}
+
+#[tokio::test]
+async fn empty_file_finishes_the_upload() {
+    let path = temp_file("upload-empty", &[]);
+    let server = MockServer::start([MockResponse::new(308), MockResponse::new(201)])
+        .await
+        .unwrap();
+    let (builder, clock) = mock_client(DefaultPolicy::default());
+    let client = builder.build().unwrap();
+
+    let upload = ResumableUpload::from_session(server.url("/session"), 0);
+    let response = upload.upload_file(&client, &path).await.unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(response.status(), 201);
+    assert!(clock.sleeps().is_empty());
+    let requests = server.received_requests();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[1].headers["content-range"], "bytes */0");
+}
+
+#[tokio::test]
+async fn committed_range_beyond_the_file_is_an_error() {
+    let path = temp_file("upload-beyond", &[0; 10]);
+    let server = MockServer::start([MockResponse::new(308).header("Range", "bytes=0-19")])
+        .await
+        .unwrap();
+    let (builder, _clock) = mock_client(DefaultPolicy::default());
+    let client = builder.build().unwrap();
+
+    let upload = ResumableUpload::from_session(server.url("/session"), 10);
+    let error = upload.upload_file(&client, &path).await.unwrap_err();
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(
+        matches!(&error, ReqwestBackoffError::InvalidHeader { name, .. } if name == "range"),
+        "{error:?}"
+    );
+}
+
+#[tokio::test]
+async fn chunks_without_progress_count_as_failed_attempts() {
+    let path = temp_file("upload-stuck", &[0; 10]);
+    let server = MockServer::start(vec![MockResponse::new(308); 10])
+        .await
+        .unwrap();
+    let config = BackoffConfig::default().with_max_attempts(3);
+    let (builder, clock) = mock_client(DefaultPolicy::new(config));
+    let client = builder.build().unwrap();
+
+    let upload = ResumableUpload::from_session(server.url("/session"), 10);
+    let error = upload.upload_file(&client, &path).await.unwrap_err();
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(
+        matches!(
+            error,
+            ReqwestBackoffError::BackoffExceeded {
+                backoff_attempts: 3,
+                ..
+            }
+        ),
+        "{error:?}"
+    );
+    // The status query and three chunks
+    assert_eq!(server.received_requests().len(), 4);
+    assert_eq!(clock.sleeps().len(), 2);
+}
+
+#[tokio::test]
+async fn status_queries_after_a_failed_chunk_share_the_attempts() {
+    let path = temp_file("upload-shared", &[0; 10]);
+    let server = MockServer::start([
+        MockResponse::new(308),
+        MockResponse::new(503),
+        MockResponse::new(503),
+        MockResponse::new(308).header("Range", "bytes=0-4"),
+        MockResponse::new(201),
+    ])
+    .await
+    .unwrap();
+    let config = BackoffConfig::default().with_max_attempts(2);
+    let (builder, clock) = mock_client(DefaultPolicy::new(config));
+    let client = builder.build().unwrap();
+
+    let upload = ResumableUpload::from_session(server.url("/session"), 10);
+    let error = upload.upload_file(&client, &path).await.unwrap_err();
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(
+        matches!(
+            error,
+            ReqwestBackoffError::BackoffExceeded {
+                backoff_attempts: 2,
+                ..
+            }
+        ),
+        "{error:?}"
+    );
+    let requests = server.received_requests();
+    assert_eq!(requests.len(), 3);
+    assert_eq!(requests[1].headers["content-range"], "bytes 0-9/10");
+    assert_eq!(requests[2].headers["content-range"], "bytes */10");
+    assert_eq!(clock.sleeps().len(), 1);
+}